@@ -0,0 +1,561 @@
+//! Which keys, mouse buttons, mouse movements and gamepad controls do what.
+//!
+//! Keys are named by where they are on the keyboard, as by the DOM's `KeyboardEvent.code`,
+//! e.g. `KeyW` for the key that is W on a QWERTY keyboard, so the default controls sit in the
+//! same place on any layout, and don't change while Shift is held.
+//!
+//! # Text format, version 1
+//!
+//! ```text
+//! four-dimensions controls 1
+//! key KeyW move-forward
+//! mouse 0 break-block
+//! axis mouse-x look-right 0.003
+//! axis mouse-y look-up 0.003 inverted
+//! gamepad 0 move-up
+//! axis gamepad-1 move-backward 1
+//! dead-zone 0.15
+//! ```
+//!
+//! - `key CODE ACTION` binds a key to an action. A key may only be bound once, but an action may be bound to many keys.
+//! - `mouse BUTTON ACTION` does the same for a mouse button, numbered as by `MouseEvent.button`.
+//! - `gamepad BUTTON ACTION` does the same for a gamepad button, numbered as by `Gamepad.buttons`.
+//! - `axis AXIS ACTION SENSITIVITY [inverted]` binds an axis to an action, which it does by an amount.
+//!   - `mouse-x`, `mouse-y` and `wheel` count movement right, up and scrolling down as positive.
+//!     They can only be bound to the `look-*` actions, with the sensitivity in radians per pixel.
+//!   - `gamepad-N` is an axis of a gamepad, numbered as by `Gamepad.axes`.
+//!     In the standard mapping, 0 and 1 are the left stick, and 2 and 3 the right stick, counting right and down as positive.
+//!     They can be bound to the `look-*` actions, with the sensitivity in radians per second when pushed all the way,
+//!     or the `move-*` actions, with the sensitivity as a fraction of full speed.
+//!
+//!   An axis may only be bound once. Moving it the wrong way does the opposite action, e.g. looking left instead of right.
+//! - `dead-zone AMOUNT` is how far a gamepad axis must be pushed, from 0 to 1, before it does anything.
+//! - Blank lines are ignored. The file replaces all of the default controls, rather than adding to them.
+
+use crate::world::Block;
+
+const TEXT_HEADER: &str = "four-dimensions controls ";
+const VERSION: u32 = 1;
+
+/// Something the player can do.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    /// Also jumps, while walking.
+    MoveUp,
+    MoveDown,
+    MoveAna,
+    MoveKata,
+    /// Turn in the x-w plane. Buttons bound to this and the other `Look*` actions turn at a fixed speed.
+    LookRight,
+    LookLeft,
+    /// Tilt in the y-w plane.
+    LookUp,
+    LookDown,
+    /// Turn in the z-w plane.
+    LookAna,
+    LookKata,
+    /// Turn the camera in free look, turning the first axis towards the second.
+    TurnXY,
+    TurnYX,
+    TurnZY,
+    TurnYZ,
+    TurnXZ,
+    TurnZX,
+    RotateScreenLeft,
+    RotateScreenRight,
+    RotateScreenUp,
+    RotateScreenDown,
+    ChangeMovement,
+    ToggleFreeLook,
+    LevelHorizon,
+    BreakBlock,
+    PlaceBlock,
+    /// Choose the block to place, by its id.
+    SelectBlock(usize),
+}
+
+/// The actions with a fixed name, for the text format. `SelectBlock(n)` is named `select-block-n`.
+const ACTION_NAMES: [(Action, &str); 29] = [
+    (Action::MoveForward, "move-forward"),
+    (Action::MoveBackward, "move-backward"),
+    (Action::MoveLeft, "move-left"),
+    (Action::MoveRight, "move-right"),
+    (Action::MoveUp, "move-up"),
+    (Action::MoveDown, "move-down"),
+    (Action::MoveAna, "move-ana"),
+    (Action::MoveKata, "move-kata"),
+    (Action::LookRight, "look-right"),
+    (Action::LookLeft, "look-left"),
+    (Action::LookUp, "look-up"),
+    (Action::LookDown, "look-down"),
+    (Action::LookAna, "look-ana"),
+    (Action::LookKata, "look-kata"),
+    (Action::TurnXY, "turn-x-y"),
+    (Action::TurnYX, "turn-y-x"),
+    (Action::TurnZY, "turn-z-y"),
+    (Action::TurnYZ, "turn-y-z"),
+    (Action::TurnXZ, "turn-x-z"),
+    (Action::TurnZX, "turn-z-x"),
+    (Action::RotateScreenLeft, "rotate-screen-left"),
+    (Action::RotateScreenRight, "rotate-screen-right"),
+    (Action::RotateScreenUp, "rotate-screen-up"),
+    (Action::RotateScreenDown, "rotate-screen-down"),
+    (Action::ChangeMovement, "change-movement"),
+    (Action::ToggleFreeLook, "toggle-free-look"),
+    (Action::LevelHorizon, "level-horizon"),
+    (Action::BreakBlock, "break-block"),
+    (Action::PlaceBlock, "place-block"),
+];
+
+const SELECT_BLOCK: &str = "select-block-";
+
+impl Action {
+    pub fn name(self) -> String {
+        match self {
+            Action::SelectBlock(id) => format!("{}{}", SELECT_BLOCK, id),
+            _ => ACTION_NAMES
+                .iter()
+                .find(|&&(action, _)| action == self)
+                .map_or_else(String::new, |&(_, name)| name.to_string()),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(id) = name.strip_prefix(SELECT_BLOCK) {
+            return id
+                .parse()
+                .ok()
+                .filter(|&id| Block::from_id(id).is_some())
+                .map(Action::SelectBlock);
+        }
+        ACTION_NAMES
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map(|&(action, _)| action)
+    }
+
+    pub const MOVE: [Action; 8] = [
+        Action::MoveRight,
+        Action::MoveLeft,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveAna,
+        Action::MoveKata,
+        Action::MoveForward,
+        Action::MoveBackward,
+    ];
+
+    pub const LOOK: [Action; 6] = [
+        Action::LookRight,
+        Action::LookLeft,
+        Action::LookUp,
+        Action::LookDown,
+        Action::LookAna,
+        Action::LookKata,
+    ];
+
+    /// For the actions that move the player, which axis they move along, relative to the camera, and which way.
+    pub fn movement(self) -> Option<(usize, f64)> {
+        match self {
+            Action::MoveRight => Some((0, 1.)),
+            Action::MoveLeft => Some((0, -1.)),
+            Action::MoveUp => Some((1, 1.)),
+            Action::MoveDown => Some((1, -1.)),
+            Action::MoveAna => Some((2, 1.)),
+            Action::MoveKata => Some((2, -1.)),
+            Action::MoveForward => Some((3, 1.)),
+            Action::MoveBackward => Some((3, -1.)),
+            _ => None,
+        }
+    }
+
+    /// For the actions that turn the camera by an amount, and so can be bound to an axis,
+    /// which of the x-w, y-w and z-w planes they turn in, and which way.
+    pub fn look(self) -> Option<(usize, f64)> {
+        match self {
+            Action::LookRight => Some((0, 1.)),
+            Action::LookLeft => Some((0, -1.)),
+            Action::LookUp => Some((1, 1.)),
+            Action::LookDown => Some((1, -1.)),
+            Action::LookAna => Some((2, 1.)),
+            Action::LookKata => Some((2, -1.)),
+            _ => None,
+        }
+    }
+}
+
+/// Something that can be held down.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Button {
+    /// A key, named as by the DOM's `KeyboardEvent.code`.
+    Key(String),
+    /// A mouse button, numbered as by the DOM's `MouseEvent.button`.
+    Mouse(i16),
+    /// A gamepad button, numbered as by the Gamepad API's `Gamepad.buttons`.
+    Gamepad(u32),
+}
+
+/// Something that moves by an amount.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Axis {
+    /// Movement of the mouse to the right, in pixels.
+    MouseX,
+    /// Movement of the mouse upwards, in pixels.
+    MouseY,
+    /// Scrolling down, in pixels.
+    Wheel,
+    /// How far a gamepad axis is pushed, from -1 to 1, numbered as by the Gamepad API's `Gamepad.axes`.
+    Gamepad(u32),
+}
+
+/// The axes with a fixed name, for the text format. `Gamepad(n)` is named `gamepad-n`.
+const AXIS_NAMES: [(Axis, &str); 3] = [
+    (Axis::MouseX, "mouse-x"),
+    (Axis::MouseY, "mouse-y"),
+    (Axis::Wheel, "wheel"),
+];
+
+const GAMEPAD_AXIS: &str = "gamepad-";
+
+impl Axis {
+    pub fn name(self) -> String {
+        match self {
+            Axis::Gamepad(index) => format!("{}{}", GAMEPAD_AXIS, index),
+            _ => AXIS_NAMES
+                .iter()
+                .find(|&&(axis, _)| axis == self)
+                .map_or_else(String::new, |&(_, name)| name.to_string()),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(index) = name.strip_prefix(GAMEPAD_AXIS) {
+            return index.parse().ok().map(Axis::Gamepad);
+        }
+        AXIS_NAMES
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map(|&(axis, _)| axis)
+    }
+
+    /// Whether the action can be bound to this axis.
+    pub fn can_bind(self, action: Action) -> bool {
+        match self {
+            Axis::Gamepad(_) => action.look().is_some() || action.movement().is_some(),
+            _ => action.look().is_some(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AxisBinding {
+    pub axis: Axis,
+    pub action: Action,
+    /// How much the action is done per unit the axis moves; see the module documentation.
+    pub sensitivity: f64,
+    pub inverted: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Controls {
+    pub buttons: Vec<(Button, Action)>,
+    pub axes: Vec<AxisBinding>,
+    /// How far a gamepad axis must be pushed, from 0 to 1, before it does anything.
+    pub dead_zone: f64,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        let key = |code: &str, action| (Button::Key(code.to_string()), action);
+        let mut buttons = vec![
+            key("KeyW", Action::MoveForward),
+            key("KeyS", Action::MoveBackward),
+            key("KeyA", Action::MoveLeft),
+            key("KeyD", Action::MoveRight),
+            key("Space", Action::MoveUp),
+            key("ShiftLeft", Action::MoveDown),
+            key("ShiftRight", Action::MoveDown),
+            key("KeyQ", Action::MoveAna),
+            key("KeyE", Action::MoveKata),
+            key("KeyZ", Action::TurnXY),
+            key("KeyX", Action::TurnYX),
+            key("KeyC", Action::TurnZY),
+            key("KeyV", Action::TurnYZ),
+            key("KeyR", Action::TurnXZ),
+            key("KeyT", Action::TurnZX),
+            key("ArrowLeft", Action::RotateScreenLeft),
+            key("ArrowRight", Action::RotateScreenRight),
+            key("ArrowUp", Action::RotateScreenUp),
+            key("ArrowDown", Action::RotateScreenDown),
+            key("KeyF", Action::ChangeMovement),
+            key("KeyL", Action::ToggleFreeLook),
+            key("KeyH", Action::LevelHorizon),
+            (Button::Mouse(0), Action::BreakBlock),
+            (Button::Mouse(2), Action::PlaceBlock),
+        ];
+        // Buttons as numbered in the standard gamepad mapping.
+        // The sticks move and look, the triggers move up and down, and the shoulder buttons look ana and kata.
+        for &(button, action) in &[
+            (0, Action::MoveUp),
+            (1, Action::PlaceBlock),
+            (2, Action::BreakBlock),
+            (3, Action::ToggleFreeLook),
+            (4, Action::LookKata),
+            (5, Action::LookAna),
+            (6, Action::MoveDown),
+            (7, Action::MoveUp),
+            (8, Action::ChangeMovement),
+            (10, Action::LevelHorizon),
+            (12, Action::MoveAna),
+            (13, Action::MoveKata),
+            (14, Action::RotateScreenLeft),
+            (15, Action::RotateScreenRight),
+        ] {
+            buttons.push((Button::Gamepad(button), action));
+        }
+        // The number keys choose a block, in the order of `Block::ALL`, skipping air.
+        for block in Block::ALL.iter().skip(1).take(9) {
+            buttons.push(key(
+                &format!("Digit{}", block.id()),
+                Action::SelectBlock(block.id()),
+            ));
+        }
+
+        let axis = |axis, action, sensitivity| AxisBinding {
+            axis,
+            action,
+            sensitivity,
+            inverted: false,
+        };
+        let axes = vec![
+            axis(Axis::MouseX, Action::LookRight, 3e-3),
+            axis(Axis::MouseY, Action::LookUp, 3e-3),
+            axis(Axis::Wheel, Action::LookAna, 1e-2),
+            axis(Axis::Gamepad(0), Action::MoveRight, 1.),
+            axis(Axis::Gamepad(1), Action::MoveBackward, 1.),
+            axis(Axis::Gamepad(2), Action::LookRight, 2.),
+            axis(Axis::Gamepad(3), Action::LookDown, 2.),
+        ];
+
+        Self {
+            buttons,
+            axes,
+            dead_zone: 0.15,
+        }
+    }
+}
+
+impl Controls {
+    /// The action bound to a button, if any.
+    pub fn action(&self, button: &Button) -> Option<Action> {
+        self.buttons
+            .iter()
+            .find(|(b, _)| b == button)
+            .map(|&(_, action)| action)
+    }
+
+    /// Bind a button to an action, replacing what it was bound to before.
+    pub fn bind(&mut self, button: Button, action: Action) {
+        self.buttons.retain(|(b, _)| *b != button);
+        self.buttons.push((button, action));
+    }
+
+    /// How much an axis turns the camera, and how, when it moves by `amount`.
+    pub fn axis(&self, axis: Axis, amount: f64) -> Option<(Action, f64)> {
+        let binding = self.axes.iter().find(|binding| binding.axis == axis)?;
+        let sign = if binding.inverted { -1. } else { 1. };
+        Some((binding.action, amount * binding.sensitivity * sign))
+    }
+
+    /// What a gamepad axis does, and how much, when pushed to `value`, allowing for the dead zone.
+    pub fn gamepad_axis(&self, index: u32, value: f64) -> Option<(Action, f64)> {
+        let dead_zone = self.dead_zone.clamp(0., 0.99);
+        let value = if value.abs() <= dead_zone {
+            0.
+        } else {
+            value.signum() * (value.abs() - dead_zone) / (1. - dead_zone)
+        };
+        self.axis(Axis::Gamepad(index), value)
+    }
+
+    pub fn load(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+
+        let header = lines.next().unwrap_or("");
+        if !header.starts_with(TEXT_HEADER) {
+            return Err("Not a controls file".into());
+        }
+        let version = &header[TEXT_HEADER.len()..];
+        if version.trim().parse::<u32>() != Ok(VERSION) {
+            return Err(format!("Unsupported controls version `{}`", version.trim()));
+        }
+
+        let mut out = Self {
+            buttons: Vec::new(),
+            axes: Vec::new(),
+            dead_zone: Self::default().dead_zone,
+        };
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let action = |name: &str| {
+                Action::from_name(name).ok_or_else(|| format!("Unknown action `{}`", name))
+            };
+            let button = match words[..] {
+                [] => continue,
+                ["key", code, name] => (Button::Key(code.to_string()), action(name)?),
+                ["mouse", number, name] => {
+                    let number = number
+                        .parse()
+                        .map_err(|_| format!("Could not parse mouse button `{}`", number))?;
+                    (Button::Mouse(number), action(name)?)
+                }
+                ["gamepad", number, name] => {
+                    let number = number
+                        .parse()
+                        .map_err(|_| format!("Could not parse gamepad button `{}`", number))?;
+                    (Button::Gamepad(number), action(name)?)
+                }
+                ["dead-zone", amount] => {
+                    out.dead_zone = amount
+                        .parse()
+                        .ok()
+                        .filter(|amount| (0. ..1.).contains(amount))
+                        .ok_or_else(|| format!("Could not parse dead zone `{}`", amount))?;
+                    continue;
+                }
+                ["axis", axis, name, sensitivity] | ["axis", axis, name, sensitivity, _] => {
+                    let inverted = match words.get(4) {
+                        None => false,
+                        Some(&"inverted") => true,
+                        Some(word) => return Err(format!("Unexpected `{}` after axis", word)),
+                    };
+                    let binding = AxisBinding {
+                        axis: Axis::from_name(axis)
+                            .ok_or_else(|| format!("Unknown axis `{}`", axis))?,
+                        action: action(name)?,
+                        sensitivity: sensitivity.parse().map_err(|_| {
+                            format!("Could not parse sensitivity `{}`", sensitivity)
+                        })?,
+                        inverted,
+                    };
+                    if !binding.axis.can_bind(binding.action) {
+                        return Err(format!("Can't bind `{}` to `{}`", axis, name));
+                    }
+                    if out.axes.iter().any(|b| b.axis == binding.axis) {
+                        return Err(format!("Axis `{}` is bound twice", axis));
+                    }
+                    out.axes.push(binding);
+                    continue;
+                }
+                _ => return Err(format!("Invalid line `{}`", line)),
+            };
+            if out.action(&button.0).is_some() {
+                return Err(format!("Bound twice: `{}`", line));
+            }
+            out.buttons.push(button);
+        }
+        Ok(out)
+    }
+
+    pub fn save(&self) -> String {
+        let mut out = format!("{}{}\n", TEXT_HEADER, VERSION);
+        for (button, action) in &self.buttons {
+            match button {
+                Button::Key(code) => out += &format!("key {} {}\n", code, action.name()),
+                Button::Mouse(number) => out += &format!("mouse {} {}\n", number, action.name()),
+                Button::Gamepad(number) => {
+                    out += &format!("gamepad {} {}\n", number, action.name())
+                }
+            }
+        }
+        for binding in &self.axes {
+            out += &format!(
+                "axis {} {} {}{}\n",
+                binding.axis.name(),
+                binding.action.name(),
+                binding.sensitivity,
+                if binding.inverted { " inverted" } else { "" }
+            );
+        }
+        out += &format!("dead-zone {}\n", self.dead_zone);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(lines: &str) -> Result<Controls, String> {
+        Controls::load(&format!("{}{}\n{}", TEXT_HEADER, VERSION, lines))
+    }
+
+    #[test]
+    fn default_round_trip() {
+        assert_eq!(
+            Controls::load(&Controls::default().save()),
+            Ok(Controls::default())
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let controls = load(
+            "key Digit3 select-block-3\n\
+             \n\
+             axis mouse-y look-up 0.5 inverted\n\
+             axis gamepad-0 move-right 1\n\
+             dead-zone 0.25\n",
+        )
+        .unwrap();
+        assert_eq!(
+            controls.action(&Button::Key("Digit3".into())),
+            Some(Action::SelectBlock(3))
+        );
+        assert_eq!(controls.axis(Axis::MouseY, 2.), Some((Action::LookUp, -1.)));
+        assert_eq!(controls.dead_zone, 0.25);
+        assert_eq!(controls.gamepad_axis(0, 0.2), Some((Action::MoveRight, 0.)));
+        assert_eq!(
+            controls.gamepad_axis(0, -1.),
+            Some((Action::MoveRight, -1.))
+        );
+        assert_eq!(Controls::load(&controls.save()), Ok(controls));
+    }
+
+    #[test]
+    fn errors() {
+        for &(lines, error) in &[
+            (
+                "key KeyW move-up\nkey KeyW move-down\n",
+                "Bound twice: `key KeyW move-down`",
+            ),
+            (
+                "axis wheel look-ana 1\naxis wheel look-kata 1\n",
+                "Axis `wheel` is bound twice",
+            ),
+            (
+                "axis mouse-x move-right 1\n",
+                "Can't bind `mouse-x` to `move-right`",
+            ),
+            ("key KeyW fly\n", "Unknown action `fly`"),
+            (
+                "select-block-1 KeyW\n",
+                "Invalid line `select-block-1 KeyW`",
+            ),
+        ] {
+            assert_eq!(load(lines), Err(error.to_string()));
+        }
+        assert_eq!(
+            Controls::load("four-dimensions controls 2\n"),
+            Err("Unsupported controls version `2`".to_string())
+        );
+        assert_eq!(
+            Controls::load("key KeyW move-up\n"),
+            Err("Not a controls file".to_string())
+        );
+    }
+}
@@ -0,0 +1,836 @@
+use crate::controls::{Action, Axis, Button, Controls};
+use crate::phase::{Event, Phase};
+use crate::projector::{self, Projector};
+use crate::{physics, raycast, rotation, timestep, touch, world};
+use core::f64::consts::*;
+use std::collections::HashSet;
+
+use nalgebra as na;
+
+/// The state of the game, independent of how it is displayed or controlled.
+pub struct Game {
+    phase: Phase,
+    /// How long the game has been played, in seconds, not counting pauses.
+    time: f64,
+    /// How many edible blocks are left in the world.
+    edible: usize,
+    /// How many edible blocks the player has eaten.
+    eaten: usize,
+    /// How many edible blocks the world started with.
+    /// A world that started with none can't be won.
+    goals: usize,
+
+    /// Which keys and mouse buttons do what.
+    pub controls: Controls,
+    /// The keys and mouse buttons being held down.
+    pressed: HashSet<Button>,
+    /// How far each axis of the gamepad is pushed, from -1 to 1.
+    gamepad_axes: Vec<f64>,
+    touches: touch::Touches,
+    timestep: timestep::FixedTimestep,
+
+    projector: Box<dyn Projector>,
+    /// The visible parts of the world, as of the most recent projection to finish.
+    triangles: Vec<render_4d::Triangle>,
+    /// Counts the changes to `triangles`.
+    generation: u64,
+    /// Whether the camera has moved since the last projection was requested.
+    stale: bool,
+    /// Whether a projection has been requested, but hasn't finished.
+    pending: bool,
+
+    world: world::World,
+    //
+    pub screen_theta: f64,
+    pub screen_phi: f64,
+    /// The width of the display divided by its height.
+    aspect_ratio: f64,
+    //
+    pub position: na::Vector4<f64>,
+    /// The position as of the previous simulation step.
+    previous_position: na::Vector4<f64>,
+    /// The position the world is drawn from, interpolated between steps.
+    camera_position: na::Vector4<f64>,
+    velocity: na::Vector4<f64>,
+    on_ground: bool,
+    pub movement: physics::Movement,
+    collider: physics::Collider,
+    /// Which way the camera faces: this takes the camera's axes to the world's.
+    /// The camera looks along its w axis, with its y axis up.
+    pub orientation: rotation::Rotation4,
+    /// Whether the camera can turn in any direction, rather than keeping the horizon level.
+    pub free_look: bool,
+    /// The block that the right mouse button places.
+    pub selected: world::Block,
+    /// Turning that hasn't yet been applied to the orientation, as
+    /// rotations in the x-w, y-w, and z-w planes.
+    look: [f64; 3],
+}
+
+/// The length of a simulation step, in seconds.
+const TIMESTEP: f64 = 1. / 120.;
+
+/// How far away the player can break and place blocks.
+const REACH: f64 = 6.;
+
+/// How fast the keys turn the camera, in radians per second.
+const TURN_SPEED: f64 = 1.;
+
+/// Something the player did.
+pub enum Input {
+    /// A key was pressed, named by where it is on the keyboard, as by the DOM's `KeyboardEvent.code`,
+    /// e.g. `"KeyW"`, `"Space"` or `"ArrowLeft"`.
+    KeyDown(String),
+    KeyUp(String),
+    /// The mouse moved by this many pixels. This should only be sent while the mouse is captured.
+    MouseMove([f64; 2]),
+    /// The scroll wheel moved by this many pixels. This should only be sent while the mouse is captured.
+    MouseWheel(f64),
+    /// A mouse button was pressed, numbered as by the DOM's `MouseEvent.button`:
+    /// 0 is the left button, 1 the middle one, and 2 the right one.
+    /// This should only be sent while the mouse is captured.
+    MouseDown(i16),
+    MouseUp(i16),
+    /// Which of the gamepad's buttons are pressed, and how far each of its axes is pushed, numbered as by the Gamepad API.
+    /// This should be sent every frame while a gamepad is connected, and once empty after it is disconnected.
+    Gamepad {
+        buttons: Vec<bool>,
+        axes: Vec<f64>,
+    },
+    /// A finger touched the display, at a position given as a fraction of its width and height, from the top left.
+    /// See `touch` for what the fingers do.
+    TouchStart {
+        id: i64,
+        position: [f64; 2],
+    },
+    TouchMove {
+        id: i64,
+        position: [f64; 2],
+    },
+    /// A finger was lifted, or the touch was cancelled.
+    TouchEnd {
+        id: i64,
+    },
+}
+
+/// Everything needed to draw the game.
+pub struct Scene<'a> {
+    /// The visible parts of the world, projected onto the 3D screen.
+    pub triangles: &'a [render_4d::Triangle],
+    /// Changes whenever `triangles` does, so that a renderer can keep them uploaded in the meantime.
+    pub generation: u64,
+    /// How the 3D screen is rotated, as seen by the viewer.
+    pub screen: na::Matrix4<f64>,
+}
+
+impl Game {
+    /// A game that finds the visible triangles on the calling thread, as part of `scene`.
+    pub fn new(world: world::World) -> Self {
+        let projector = projector::Synchronous::new(world.clone());
+        Self::with_projector(world, Box::new(projector))
+    }
+
+    /// A game that finds the visible triangles with `projector`,
+    /// which should start with a copy of `world`.
+    pub fn with_projector(world: world::World, projector: Box<dyn Projector>) -> Self {
+        let spawn = world.spawn;
+
+        Game {
+            phase: Phase::Title,
+            time: 0.,
+            edible: world.edible_count(),
+            eaten: 0,
+            goals: world.edible_count(),
+            controls: Controls::default(),
+            pressed: HashSet::new(),
+            gamepad_axes: Vec::new(),
+            touches: touch::Touches::default(),
+            timestep: timestep::FixedTimestep::new(TIMESTEP),
+            projector,
+            triangles: Vec::new(),
+            generation: 0,
+            stale: true,
+            pending: false,
+            world,
+            //
+            screen_theta: 0.3,
+            screen_phi: -0.2,
+            aspect_ratio: 1.,
+            //
+            position: spawn.position,
+            previous_position: spawn.position,
+            camera_position: spawn.position,
+            velocity: na::Vector4::zeros(),
+            on_ground: false,
+            movement: physics::Movement::Fly,
+            collider: physics::Collider {
+                min: na::Vector4::new(-0.2, -0.4, -0.2, -0.2),
+                max: na::Vector4::new(0.2, 0.2, 0.2, 0.2),
+            },
+            orientation: rotation::horizontal(&spawn.horizontal_orientation)
+                * rotation::vertical(spawn.vertical_angle),
+            free_look: false,
+            selected: world::Block::Stone,
+            look: [0.; 3],
+        }
+    }
+
+    /// Start again in `world`, as in `with_projector`, keeping the controls and the 3D screen.
+    /// Play starts once the new world's first picture is ready.
+    pub fn restart(&mut self, world: world::World, projector: Box<dyn Projector>) {
+        let mut game = Self::with_projector(world, projector);
+        game.phase = Phase::Restarting;
+        game.controls = self.controls.clone();
+        game.screen_theta = self.screen_theta;
+        game.screen_phi = self.screen_phi;
+        game.resize(self.aspect_ratio, 1.);
+        // Keep counting, so that a renderer doesn't mistake the new triangles for the old ones.
+        game.generation = self.generation;
+        *self = game;
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Move to another phase, if `event` makes sense in this one.
+    pub fn handle(&mut self, event: Event) {
+        let phase = self.phase.next(event);
+        if phase == self.phase {
+            return;
+        }
+        self.phase = phase;
+        // Whatever is held down now will be released while not playing, without the game hearing of it.
+        if phase != Phase::Playing {
+            self.pressed.clear();
+            self.gamepad_axes.clear();
+            self.touches = touch::Touches::default();
+            self.touches.resize(self.aspect_ratio);
+            self.look = [0.; 3];
+        }
+    }
+
+    /// How long the game has been played, in seconds, not counting pauses.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// How many tesseracts have been eaten, and how many are left.
+    pub fn tesseracts(&self) -> (usize, usize) {
+        (self.eaten, self.edible)
+    }
+
+    pub fn world(&self) -> &world::World {
+        &self.world
+    }
+
+    /// The touch joysticks, for drawing over the display.
+    pub fn joysticks(&self) -> [touch::Joystick; 2] {
+        self.touches.joysticks()
+    }
+
+    /// The visible parts of the world, as seen from the current camera.
+    ///
+    /// If the projector works in the background, this may show where the camera was a few frames ago.
+    /// Only one projection is requested at a time, so that the projector never falls behind.
+    pub fn scene(&mut self) -> Scene<'_> {
+        self.receive();
+        if self.stale && !self.pending {
+            self.projector.request(self.projection_matrix());
+            self.stale = false;
+            self.pending = true;
+            // A synchronous projector has already finished.
+            self.receive();
+        }
+
+        Scene {
+            triangles: &self.triangles,
+            generation: self.generation,
+            screen: self.screen_matrix(),
+        }
+    }
+
+    fn receive(&mut self) {
+        if let Some(triangles) = self.projector.poll() {
+            self.triangles = triangles;
+            self.generation += 1;
+            self.pending = false;
+            self.handle(Event::Ready);
+        }
+    }
+
+    /// Tell the game the size of the display, in pixels, so the picture isn't stretched.
+    pub fn resize(&mut self, width: f64, height: f64) {
+        if width > 0. && height > 0. {
+            self.aspect_ratio = width / height;
+            self.touches.resize(self.aspect_ratio);
+        }
+    }
+
+    /// Call this after changing the camera, so that `scene` recomputes the visible triangles.
+    pub fn needs_rerender(&mut self) {
+        self.stale = true;
+    }
+
+    /// Inputs are ignored unless the game is being played.
+    pub fn update(&mut self, input: Input) {
+        if self.phase != Phase::Playing {
+            return;
+        }
+        match input {
+            Input::KeyDown(code) => self.press(Button::Key(code)),
+            Input::KeyUp(code) => {
+                self.pressed.remove(&Button::Key(code));
+            }
+            Input::MouseMove([x, y]) => {
+                self.move_axis(Axis::MouseX, x);
+                self.move_axis(Axis::MouseY, -y);
+            }
+            Input::MouseWheel(z) => self.move_axis(Axis::Wheel, z),
+            Input::MouseDown(button) => self.press(Button::Mouse(button)),
+            Input::MouseUp(button) => {
+                self.pressed.remove(&Button::Mouse(button));
+            }
+            Input::Gamepad { buttons, axes } => {
+                for (i, &down) in buttons.iter().enumerate() {
+                    let button = Button::Gamepad(i as u32);
+                    if down {
+                        self.press(button);
+                    } else {
+                        self.pressed.remove(&button);
+                    }
+                }
+                // Release the buttons of a gamepad that has gone away.
+                self.pressed.retain(|button| match *button {
+                    Button::Gamepad(i) => (i as usize) < buttons.len(),
+                    _ => true,
+                });
+                self.gamepad_axes = axes;
+            }
+            Input::TouchStart { id, position } => self.touches.start(id, position),
+            Input::TouchMove { id, position } => {
+                let gesture = self.touches.move_to(id, position);
+                for (look, turn) in self.look.iter_mut().zip(&gesture.look) {
+                    *look += turn;
+                }
+                let [theta, phi] = gesture.screen;
+                if [theta, phi] != [0.; 2] {
+                    self.screen_theta += theta;
+                    self.screen_phi = (self.screen_phi + phi).clamp(-FRAC_PI_2, FRAC_PI_2);
+                    self.needs_rerender();
+                }
+            }
+            Input::TouchEnd { id } => self.touches.end(id),
+        }
+    }
+
+    fn press(&mut self, button: Button) {
+        let action = self.controls.action(&button);
+        // Ignore key repeat.
+        if !self.pressed.insert(button) {
+            return;
+        }
+        match action {
+            Some(Action::ChangeMovement) => {
+                self.movement = self.movement.next();
+                self.velocity = na::Vector4::zeros();
+            }
+            Some(Action::ToggleFreeLook) => {
+                self.free_look = !self.free_look;
+                if !self.free_look {
+                    self.level_horizon();
+                }
+            }
+            Some(Action::LevelHorizon) => self.level_horizon(),
+            Some(Action::BreakBlock) => self.break_block(),
+            Some(Action::PlaceBlock) => self.place_block(),
+            Some(Action::SelectBlock(id)) => {
+                if let Some(block) = world::Block::from_id(id) {
+                    self.selected = block;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn move_axis(&mut self, axis: Axis, amount: f64) {
+        if let Some((action, angle)) = self.controls.axis(axis, amount) {
+            if let Some((plane, sign)) = action.look() {
+                self.look[plane] += angle * sign;
+            }
+        }
+    }
+
+    /// Whether any of the buttons bound to `action` are held down.
+    fn held(&self, action: Action) -> bool {
+        self.pressed
+            .iter()
+            .any(|button| self.controls.action(button) == Some(action))
+    }
+
+    /// How much the gamepad's axes and the touch joysticks are doing `action`,
+    /// which is negative if they are doing the opposite.
+    fn analog(&self, action: Action) -> f64 {
+        let mut out = self.touches.analog(action);
+        for (i, &value) in self.gamepad_axes.iter().enumerate() {
+            if let Some((a, amount)) = self.controls.gamepad_axis(i as u32, value) {
+                if a == action {
+                    out += amount;
+                }
+            }
+        }
+        out
+    }
+
+    /// The block the player is looking at, if it is within reach.
+    pub fn target(&self) -> Option<raycast::Hit> {
+        let forward = self.orientation * na::Vector4::w();
+        self.world.raycast(self.camera_position, forward, REACH)
+    }
+
+    /// Turn the camera back upright, still facing the same way, with the horizon level.
+    pub fn level_horizon(&mut self) {
+        let (horizontal, angle) = rotation::level(&self.orientation);
+        self.orientation = horizontal * rotation::vertical(angle);
+        self.needs_rerender();
+    }
+
+    fn set_block(&mut self, pos: [isize; 4], block: world::Block) {
+        if let Some(b) = self.world.block_mut(pos) {
+            self.edible -= usize::from(b.material().edible);
+            self.edible += usize::from(block.material().edible);
+            *b = block;
+            self.projector.set_block(pos, block);
+            self.needs_rerender();
+        }
+    }
+
+    fn break_block(&mut self) {
+        if let Some(hit) = self.target() {
+            self.set_block(hit.cell, world::Block::Air);
+        }
+    }
+
+    fn place_block(&mut self) {
+        let pos = match self.target() {
+            Some(hit) => hit.adjacent(),
+            None => return,
+        };
+        if *self.world.block(pos) != world::Block::Air {
+            return;
+        }
+        // Don't trap the player inside a solid block.
+        if self.selected.material().solid && self.collider.cells(self.position).any(|p| p == pos) {
+            return;
+        }
+        self.set_block(pos, self.selected);
+    }
+
+    /// Let `dt` seconds of time pass.
+    /// Time only passes while the game is being played.
+    pub fn advance(&mut self, dt: f64) {
+        if self.phase != Phase::Playing {
+            return;
+        }
+        self.time += dt;
+        for _ in 0..self.timestep.advance(dt) {
+            if self.phase != Phase::Playing {
+                break;
+            }
+            self.step();
+        }
+
+        let alpha = self.timestep.alpha();
+        let camera_position =
+            self.previous_position + (self.position - self.previous_position) * alpha;
+        if camera_position != self.camera_position {
+            self.camera_position = camera_position;
+            self.needs_rerender();
+        }
+    }
+
+    /// Advance the simulation by one timestep.
+    /// This depends only on the state of the input, never on the frame rate.
+    fn step(&mut self) {
+        let dt = self.timestep.step();
+        self.previous_position = self.position;
+
+        self.turn(dt);
+        self.look();
+        self.rotate_screen(dt);
+        self.move_player(dt);
+
+        self.eat_block();
+        // Tesseracts broken rather than eaten still count, so that the game can always be won.
+        if self.edible == 0 && self.goals > 0 {
+            self.handle(Event::Win);
+        }
+    }
+
+    fn look(&mut self) {
+        let [x, y, z] = std::mem::replace(&mut self.look, [0.; 3]);
+        if [x, y, z] == [0.; 3] {
+            return;
+        }
+
+        if self.free_look {
+            // Turn relative to the camera.
+            self.orientation = rotation::renormalize(
+                &(self.orientation
+                    * rotation::plane(3, 0, x)
+                    * rotation::plane(3, 1, y)
+                    * rotation::plane(2, 3, z)),
+            );
+        } else {
+            // Turn within the horizontal hyperplane, and tilt up or down no further than vertical.
+            let (horizontal, angle) = rotation::level(&self.orientation);
+            let horizontal = horizontal * rotation::plane(3, 0, x) * rotation::plane(2, 3, z);
+            let angle = (angle + y).clamp(-FRAC_PI_2, FRAC_PI_2);
+            self.orientation = rotation::renormalize(&(horizontal * rotation::vertical(angle)));
+        }
+        self.needs_rerender();
+    }
+
+    /// Buttons and gamepad axes bound to the look actions turn the camera as the mouse would, but at a speed.
+    /// In free look, the buttons can also turn the camera in the planes that the mouse can't.
+    fn turn(&mut self, dt: f64) {
+        for &action in &Action::LOOK {
+            let speed = if self.held(action) {
+                TURN_SPEED
+            } else {
+                self.analog(action)
+            };
+            if let Some((plane, sign)) = action.look() {
+                self.look[plane] += speed * dt * sign;
+            }
+        }
+
+        if !self.free_look {
+            return;
+        }
+        let mut turn = rotation::Rotation4::identity();
+        for &(action, a, b) in &[
+            (Action::TurnXY, 0, 1),
+            (Action::TurnYX, 1, 0),
+            (Action::TurnZY, 2, 1),
+            (Action::TurnYZ, 1, 2),
+            (Action::TurnXZ, 0, 2),
+            (Action::TurnZX, 2, 0),
+        ] {
+            if self.held(action) {
+                turn *= rotation::plane(a, b, TURN_SPEED * dt);
+            }
+        }
+        if turn != rotation::Rotation4::identity() {
+            self.orientation = rotation::renormalize(&(self.orientation * turn));
+            self.needs_rerender();
+        }
+    }
+
+    fn rotate_screen(&mut self, dt: f64) {
+        if self.held(Action::RotateScreenLeft) {
+            self.screen_theta -= dt;
+        }
+        if self.held(Action::RotateScreenRight) {
+            self.screen_theta += dt;
+        }
+        if self.held(Action::RotateScreenUp) {
+            self.screen_phi += dt;
+            self.screen_phi = self.screen_phi.min(FRAC_PI_2)
+        }
+        if self.held(Action::RotateScreenDown) {
+            self.screen_phi -= dt;
+            self.screen_phi = self.screen_phi.max(-FRAC_PI_2)
+        }
+    }
+
+    fn move_player(&mut self, dt: f64) {
+        let mut input = na::Vector4::zeros();
+        for &action in &Action::MOVE {
+            // A button moves at full speed; a gamepad axis, as far as it is pushed.
+            let amount = if self.held(action) {
+                1.
+            } else {
+                self.analog(action).clamp(-1., 1.)
+            };
+            if let Some((axis, sign)) = action.movement() {
+                input[axis] += amount * sign;
+            }
+        }
+        // In free look, move relative to the camera; otherwise, relative to the horizon.
+        let frame = if self.free_look {
+            self.orientation
+        } else {
+            rotation::level(&self.orientation).0
+        };
+        let direction = frame * input;
+
+        let old_position = self.position;
+
+        match self.movement {
+            physics::Movement::Noclip => {
+                self.position += direction * dt;
+            }
+            physics::Movement::Fly => {
+                self.collider
+                    .move_and_slide(&self.world, &mut self.position, direction * dt);
+            }
+            physics::Movement::Walk => {
+                let mut target = direction * physics::WALK_SPEED;
+                target[1] = 0.;
+
+                let acceleration = if self.on_ground {
+                    physics::GROUND_ACCELERATION
+                } else {
+                    physics::AIR_ACCELERATION
+                };
+
+                let vertical_velocity = self.velocity[1];
+                self.velocity[1] = 0.;
+                self.velocity = physics::approach(self.velocity, target, acceleration * dt);
+                self.velocity[1] = vertical_velocity - physics::GRAVITY * dt;
+
+                // Pushing a stick most of the way up jumps, too.
+                if self.on_ground
+                    && (self.held(Action::MoveUp) || self.analog(Action::MoveUp) > 0.5)
+                {
+                    self.velocity[1] = physics::JUMP_SPEED;
+                }
+
+                let blocked = self.collider.move_and_slide(
+                    &self.world,
+                    &mut self.position,
+                    self.velocity * dt,
+                );
+                self.on_ground = blocked[1] && self.velocity[1] < 0.;
+                for (v, &blocked) in self.velocity.iter_mut().zip(&blocked) {
+                    if blocked {
+                        *v = 0.;
+                    }
+                }
+
+                // Don't fall forever.
+                if self.position[1] < self.world.min()[1] as f64 - 16. {
+                    self.respawn();
+                }
+            }
+        }
+
+        if self.position != old_position {
+            self.needs_rerender();
+        }
+    }
+
+    fn respawn(&mut self) {
+        let spawn = self.world.spawn;
+        self.position = spawn.position;
+        self.previous_position = spawn.position;
+        self.orientation = rotation::horizontal(&spawn.horizontal_orientation)
+            * rotation::vertical(spawn.vertical_angle);
+        self.velocity = na::Vector4::zeros();
+        self.on_ground = false;
+        self.needs_rerender();
+    }
+
+    fn screen_matrix(&self) -> na::Matrix4<f64> {
+        // Fit the 3D screen into the shorter side of the display.
+        let aspect = if self.aspect_ratio > 1. {
+            na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1. / self.aspect_ratio, 1., 1.))
+        } else {
+            na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1., self.aspect_ratio, 1.))
+        };
+
+        aspect
+            * na::Matrix4::new(
+                1.,
+                0.,
+                0.,
+                0.,
+                0.,
+                self.screen_phi.cos(),
+                -self.screen_phi.sin(),
+                0.,
+                0.,
+                self.screen_phi.sin(),
+                self.screen_phi.cos(),
+                0.,
+                0.,
+                0.,
+                0.,
+                1.,
+            )
+            * na::Matrix4::new(
+                self.screen_theta.cos(),
+                0.,
+                -self.screen_theta.sin(),
+                0.,
+                0.,
+                1.,
+                0.,
+                0.,
+                self.screen_theta.sin(),
+                0.,
+                self.screen_theta.cos(),
+                0.,
+                0.,
+                0.,
+                0.,
+                1.,
+            )
+    }
+
+    fn projection_matrix(&self) -> na::Matrix5<f64> {
+        let cotangent_half_fov = 0.5;
+        let projection: na::Matrix5<f64> = na::Matrix5::new(
+            cotangent_half_fov,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.,
+            cotangent_half_fov,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.,
+            cotangent_half_fov,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.,
+            -1.,
+            0.,
+            0.,
+            0.,
+            1.,
+            0.,
+        );
+
+        let isometry = na::Isometry::from_parts(
+            na::Translation {
+                vector: self.camera_position,
+            },
+            self.orientation,
+        )
+        .inverse();
+        projection * isometry.to_homogeneous()
+    }
+
+    fn eat_block(&mut self) {
+        for pos in self.collider.cells(self.position) {
+            if self.world.block(pos).material().edible {
+                self.set_block(pos, world::Block::Air);
+                self.eaten += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game in a small world, with a tesseract at `goal` if there is one, that has started.
+    fn game(goal: Option<[isize; 4]>) -> Game {
+        let mut world = world::World::new([0; 4], [5; 4]);
+        if let Some(goal) = goal {
+            *world.block_mut(goal).unwrap() = world::Block::Goal;
+        }
+        let mut game = Game::new(world);
+        game.handle(Event::Start);
+        game
+    }
+
+    #[test]
+    fn eating_every_tesseract_wins() {
+        // The player spawns in the middle, on top of the tesseract.
+        let mut game = game(Some([2; 4]));
+        assert_eq!(game.tesseracts(), (0, 1));
+        game.advance(0.1);
+        assert_eq!(game.tesseracts(), (1, 0));
+        assert_eq!(game.phase(), Phase::Won);
+    }
+
+    #[test]
+    fn breaking_the_last_tesseract_still_wins() {
+        let mut game = game(Some([0; 4]));
+        game.advance(0.1);
+        assert_eq!(game.phase(), Phase::Playing);
+        game.set_block([0; 4], world::Block::Air);
+        game.advance(0.1);
+        assert_eq!(game.tesseracts(), (0, 0));
+        assert_eq!(game.phase(), Phase::Won);
+    }
+
+    #[test]
+    fn a_world_without_tesseracts_is_not_won() {
+        let mut game = game(None);
+        game.advance(0.1);
+        assert_eq!(game.phase(), Phase::Playing);
+    }
+
+    /// A started game in a world with a floor, with the player above it and moving as `movement` says.
+    fn on_floor(movement: physics::Movement) -> Game {
+        let mut world = world::World::new([0; 4], [5; 4]);
+        for x in 0..5 {
+            for z in 0..5 {
+                for w in 0..5 {
+                    *world.block_mut([x, 0, z, w]).unwrap() = world::Block::Stone;
+                    // A wall in the way of moving right.
+                    *world.block_mut([4, x, z, w]).unwrap() = world::Block::Stone;
+                }
+            }
+        }
+        world.spawn.position = na::Vector4::new(2.5, 3.5, 2.5, 2.5);
+        let mut game = Game::new(world);
+        game.movement = movement;
+        game.handle(Event::Start);
+        game
+    }
+
+    fn play(game: &mut Game, seconds: f64) {
+        for _ in 0..(seconds * 10.).round() as usize {
+            game.advance(0.1);
+        }
+    }
+
+    #[test]
+    fn noclip_passes_through_blocks() {
+        let mut game = on_floor(physics::Movement::Noclip);
+        game.update(Input::KeyDown("KeyD".into()));
+        play(&mut game, 2.);
+        assert!(game.position[0] > 4., "{}", game.position);
+    }
+
+    #[test]
+    fn fly_is_stopped_by_blocks_but_not_gravity() {
+        let mut game = on_floor(physics::Movement::Fly);
+        play(&mut game, 1.);
+        assert_eq!(game.position, na::Vector4::new(2.5, 3.5, 2.5, 2.5));
+        game.update(Input::KeyDown("KeyD".into()));
+        play(&mut game, 3.);
+        assert!((game.position[0] - 3.8).abs() < 1e-9, "{}", game.position);
+    }
+
+    #[test]
+    fn walk_only_jumps_from_the_ground() {
+        let mut game = on_floor(physics::Movement::Walk);
+        game.update(Input::KeyDown("Space".into()));
+        game.advance(TIMESTEP * 1.5);
+        assert!(!game.on_ground);
+        assert!(game.velocity[1] < 0., "jumped in the air");
+
+        game.update(Input::KeyUp("Space".into()));
+        play(&mut game, 2.);
+        assert!(game.on_ground);
+        assert!((game.position[1] - 1.4).abs() < 1e-9, "{}", game.position);
+
+        game.update(Input::KeyDown("Space".into()));
+        game.advance(TIMESTEP * 1.5);
+        assert!(game.position[1] > 1.4);
+        assert!(game.velocity[1] > 0.);
+    }
+}
@@ -0,0 +1,412 @@
+//! Procedural generation of worlds.
+//!
+//! A `Generator` fills a world by running a list of stages in turn.
+//! Each stage is given its own `Noise`, derived from the seed and the stage's position in the list,
+//! so the same seed and stages always generate the same world.
+
+use crate::world::{Block, World};
+use nalgebra as na;
+
+/// The extents of the worlds generated when the player asks for a random world.
+pub const DEFAULT_MIN: [isize; 4] = [-8, 0, -8, -8];
+pub const DEFAULT_MAX: [isize; 4] = [8, 12, 8, 8];
+
+/// One step of world generation.
+pub trait Stage {
+    fn apply(&self, world: &mut World, noise: Noise);
+}
+
+pub struct Generator {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Generator {
+    /// A generator with no stages, which generates empty worlds.
+    pub fn new() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    pub fn stage(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn generate(&self, seed: u64, min: [isize; 4], max: [isize; 4]) -> World {
+        let mut world = World::new(min, max);
+        let noise = Noise::new(seed);
+        for (i, stage) in self.stages.iter().enumerate() {
+            stage.apply(&mut world, noise.derive(i as u64));
+        }
+        world
+    }
+}
+
+impl Default for Generator {
+    /// Hilly terrain with caves, floating islands above it, and goals scattered over the surface.
+    fn default() -> Self {
+        Self::new()
+            .stage(Terrain::default())
+            .stage(Caves::default())
+            .stage(FloatingIslands::default())
+            .stage(PlaceSpawn)
+            .stage(Goals::default())
+    }
+}
+
+/// Seeded four-dimensional value noise.
+#[derive(Copy, Clone, Debug)]
+pub struct Noise {
+    seed: u64,
+}
+
+/// The SplitMix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed: mix(seed) }
+    }
+
+    /// An independent noise function.
+    pub fn derive(self, n: u64) -> Self {
+        Self {
+            seed: mix(self.seed ^ mix(n)),
+        }
+    }
+
+    fn hash(self, pos: [i64; 4]) -> u64 {
+        pos.iter().fold(self.seed, |h, &x| mix(h ^ x as u64))
+    }
+
+    /// A random number in `[0, 1)`, which depends only on `pos`.
+    pub fn random(self, pos: [isize; 4]) -> f64 {
+        let h = self.hash([pos[0] as i64, pos[1] as i64, pos[2] as i64, pos[3] as i64]);
+        (h >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Smooth noise in `[-1, 1]`, varying on a scale of about one unit.
+    pub fn sample(self, pos: na::Vector4<f64>) -> f64 {
+        let floor = pos.map(f64::floor);
+        let t = (pos - floor).map(|t| t * t * t * (t * (t * 6. - 15.) + 10.));
+
+        let mut out = 0.;
+        for corner in 0..16 {
+            let mut lattice = [0; 4];
+            let mut weight = 1.;
+            for i in 0..4 {
+                let bit = (corner >> i) & 1;
+                lattice[i] = floor[i] as i64 + bit;
+                weight *= if bit == 1 { t[i] } else { 1. - t[i] };
+            }
+            let value = (self.hash(lattice) >> 11) as f64 / (1u64 << 52) as f64 - 1.;
+            out += weight * value;
+        }
+        out
+    }
+
+    /// Several octaves of noise, each at twice the frequency and half the amplitude of the last.
+    /// The result is in `[-1, 1]`.
+    pub fn fractal(self, pos: na::Vector4<f64>, octaves: u32) -> f64 {
+        let mut out = 0.;
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut frequency = 1.;
+        for octave in 0..octaves {
+            out += amplitude * self.derive(octave.into()).sample(pos * frequency);
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        if total > 0. {
+            out / total
+        } else {
+            0.
+        }
+    }
+}
+
+fn position(pos: [isize; 4]) -> na::Vector4<f64> {
+    na::Vector4::new(pos[0] as f64, pos[1] as f64, pos[2] as f64, pos[3] as f64)
+}
+
+/// Every column of the world, as the `x`, `z` and `w` coordinates with `y` set to zero.
+fn columns(world: &World) -> Vec<[isize; 4]> {
+    let (min, max) = (world.min(), world.max());
+    let mut out = Vec::new();
+    for x in min[0]..max[0] {
+        for z in min[2]..max[2] {
+            for w in min[3]..max[3] {
+                out.push([x, 0, z, w]);
+            }
+        }
+    }
+    out
+}
+
+/// The `y` coordinate of the highest non-air block in a column.
+fn surface(world: &World, mut pos: [isize; 4]) -> Option<isize> {
+    for y in (world.min()[1]..world.max()[1]).rev() {
+        pos[1] = y;
+        if *world.block(pos) != Block::Air {
+            return Some(y);
+        }
+    }
+    None
+}
+
+/// Fill the world up to a height that varies over the horizontal hyperplane.
+pub struct Terrain {
+    /// The average height, as a fraction of the way from the bottom of the world to the top.
+    pub height: f64,
+    /// How far the height varies, as a fraction of the height of the world.
+    pub amplitude: f64,
+    /// The horizontal size of the hills, in blocks.
+    pub scale: f64,
+    pub block: Block,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Self {
+            height: 0.35,
+            amplitude: 0.3,
+            scale: 12.,
+            block: Block::Stone,
+        }
+    }
+}
+
+impl Stage for Terrain {
+    fn apply(&self, world: &mut World, noise: Noise) {
+        let bottom = world.min()[1] as f64;
+        let size = (world.max()[1] - world.min()[1]) as f64;
+        for mut pos in columns(world) {
+            let height =
+                self.height + self.amplitude * noise.fractal(position(pos) / self.scale, 3);
+            let top = (bottom + height * size).round() as isize;
+            for y in world.min()[1]..top {
+                pos[1] = y;
+                if let Some(block) = world.block_mut(pos) {
+                    *block = self.block;
+                }
+            }
+        }
+    }
+}
+
+/// Carve caves out of the ground, leaving the bottom layer of the world intact.
+pub struct Caves {
+    /// The size of the caves, in blocks.
+    pub scale: f64,
+    /// Noise values above this are carved out. Higher values give fewer caves.
+    pub threshold: f64,
+}
+
+impl Default for Caves {
+    fn default() -> Self {
+        Self {
+            scale: 6.,
+            threshold: 0.3,
+        }
+    }
+}
+
+impl Stage for Caves {
+    fn apply(&self, world: &mut World, noise: Noise) {
+        for mut pos in columns(world) {
+            for y in world.min()[1] + 1..world.max()[1] {
+                pos[1] = y;
+                if *world.block(pos) != Block::Air
+                    && noise.fractal(position(pos) / self.scale, 2) > self.threshold
+                {
+                    if let Some(block) = world.block_mut(pos) {
+                        *block = Block::Air;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Add blobs of ground floating in the sky.
+pub struct FloatingIslands {
+    /// The height the islands float at, as a fraction of the way from the bottom of the world to the top.
+    pub height: f64,
+    /// How far above and below `height` the islands extend, as a fraction of the height of the world.
+    pub thickness: f64,
+    /// The size of the islands, in blocks.
+    pub scale: f64,
+    /// Noise values above this become islands. Higher values give fewer islands.
+    pub threshold: f64,
+    pub block: Block,
+}
+
+impl Default for FloatingIslands {
+    fn default() -> Self {
+        Self {
+            height: 0.75,
+            thickness: 0.15,
+            scale: 5.,
+            threshold: 0.1,
+            block: Block::Stone,
+        }
+    }
+}
+
+impl Stage for FloatingIslands {
+    fn apply(&self, world: &mut World, noise: Noise) {
+        let bottom = world.min()[1] as f64;
+        let size = (world.max()[1] - world.min()[1]) as f64;
+        let center = bottom + self.height * size;
+        let thickness = self.thickness * size;
+
+        for mut pos in columns(world) {
+            for y in world.min()[1]..world.max()[1] {
+                pos[1] = y;
+                // Islands thin out away from their central height.
+                let falloff = ((y as f64 + 0.5 - center) / thickness).powi(2);
+                if noise.fractal(position(pos) / self.scale, 2) - falloff > self.threshold {
+                    if let Some(block) = world.block_mut(pos) {
+                        *block = self.block;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Scatter goals on top of the ground, except where the player spawns.
+pub struct Goals {
+    /// The chance of each column getting a goal.
+    pub probability: f64,
+}
+
+impl Default for Goals {
+    fn default() -> Self {
+        Self { probability: 0.03 }
+    }
+}
+
+impl Stage for Goals {
+    fn apply(&self, world: &mut World, noise: Noise) {
+        let spawn = world.spawn.position.map(|x| x.floor() as isize);
+        for mut pos in columns(world) {
+            if noise.random(pos) >= self.probability {
+                continue;
+            }
+            pos[1] = surface(world, pos).map_or(world.min()[1], |y| y + 1);
+            if (0..4).all(|i| pos[i] == spawn[i]) {
+                continue;
+            }
+            if let Some(block) = world.block_mut(pos) {
+                *block = Block::Goal;
+            }
+        }
+    }
+}
+
+/// Put the spawn point above the ground in the middle of the world.
+pub struct PlaceSpawn;
+
+impl Stage for PlaceSpawn {
+    fn apply(&self, world: &mut World, _noise: Noise) {
+        let (min, max) = (world.min(), world.max());
+        let pos = [
+            (min[0] + max[0]) / 2,
+            0,
+            (min[2] + max[2]) / 2,
+            (min[3] + max[3]) / 2,
+        ];
+        let y = surface(world, pos).map_or(min[1], |y| y + 1);
+        world.spawn.position = position([pos[0], y, pos[2], pos[3]]) + na::Vector4::repeat(0.5);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(world: &World) -> Vec<Block> {
+        let (min, max) = (world.min(), world.max());
+        let mut out = Vec::new();
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    for w in min[3]..max[3] {
+                        out.push(*world.block([x, y, z, w]));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Smaller than the default, so that the tests don't take long.
+    fn generate(seed: u64) -> World {
+        Generator::default().generate(seed, [-4, 0, -4, -4], [4, 12, 4, 4])
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let (a, b) = (generate(5), generate(5));
+        assert!(cells(&a) == cells(&b));
+        assert_eq!(a.spawn.position, b.spawn.position);
+        assert!(cells(&a) != cells(&generate(6)));
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let noise = Noise::new(3);
+        for i in 0..2000 {
+            let pos = na::Vector4::new(i as f64 * 0.37, i as f64 * -1.13, i as f64 * 0.071, -7.5);
+            let sample = noise.sample(pos);
+            assert!((-1. ..=1.).contains(&sample), "{}", sample);
+            let fractal = noise.fractal(pos, 3);
+            assert!((-1. ..=1.).contains(&fractal), "{}", fractal);
+            let random = noise.random([i, -i, 2 * i, 7]);
+            assert!((0. ..1.).contains(&random), "{}", random);
+        }
+    }
+
+    #[test]
+    fn has_goals() {
+        for seed in 0..4 {
+            assert!(generate(seed).edible_count() > 0, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn spawns_in_air_above_solid_ground() {
+        for seed in 0..4 {
+            let world = generate(seed);
+            let position = world.spawn.position;
+            let cell = [
+                position[0].floor() as isize,
+                position[1].floor() as isize,
+                position[2].floor() as isize,
+                position[3].floor() as isize,
+            ];
+            for i in 0..4 {
+                assert!(
+                    (world.min()[i]..world.max()[i]).contains(&cell[i]),
+                    "seed {} spawns outside the world at {:?}",
+                    seed,
+                    cell
+                );
+            }
+            assert_eq!(*world.block(cell), Block::Air, "seed {}", seed);
+            let below = [cell[0], cell[1] - 1, cell[2], cell[3]];
+            assert!(
+                world.block(below).material().solid,
+                "seed {} spawns above {:?}",
+                seed,
+                world.block(below)
+            );
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Images, as produced by the renderers, and the file formats they can be saved in.
+//!
+//! - PNG and PPM hold the picture the player sees.
+//! - PFM holds the float buffer from the first rendering pass, before tone mapping.
+//!   It stores only the color channels, not the count of triangles in the alpha channel.
+
+/// An image of RGBA pixels, stored row by row from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image<T> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[T; 4]>,
+}
+
+impl<T: Copy> Image<T> {
+    pub fn new(width: usize, height: usize, fill: [T; 4]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    /// An image from the interleaved channels returned by OpenGL's `readPixels`,
+    /// which lists rows from the bottom.
+    pub fn from_gl(width: usize, height: usize, data: &[T]) -> Self {
+        assert_eq!(data.len(), width * height * 4, "wrong amount of pixel data");
+        let mut pixels = Vec::with_capacity(width * height);
+        // `chunks` can't make chunks of nothing, but an empty image has no rows anyway.
+        for row in data.chunks(width.max(1) * 4).rev() {
+            for p in row.chunks(4) {
+                pixels.push([p[0], p[1], p[2], p[3]]);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The pixel in column `x` and row `y`, counting rows from the top.
+    pub fn pixel(&self, x: usize, y: usize) -> [T; 4] {
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Image<f32> {
+    /// The second pass: display each channel `x` of the accumulated light as `exp(-x)`.
+    ///
+    /// The alpha channel is always opaque, since it only counts the triangles drawn over each pixel.
+    pub fn tone_map(&self) -> Image<u8> {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|&[r, g, b, _]| [to_u8((-r).exp()), to_u8((-g).exp()), to_u8((-b).exp()), 255])
+                .collect(),
+        }
+    }
+
+    /// The color channels as a little-endian PFM file.
+    pub fn to_pfm(&self) -> Vec<u8> {
+        let mut out = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        // PFM lists rows from the bottom.
+        for row in self.pixels.chunks(self.width.max(1)).rev() {
+            for pixel in row {
+                for channel in &pixel[..3] {
+                    out.extend_from_slice(&channel.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+}
+
+fn to_u8(x: f32) -> u8 {
+    (x.clamp(0., 1.) * 255.).round() as u8
+}
+
+impl Image<u8> {
+    /// Fails if the image is empty, which PNG can't represent.
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "Can't save a {}x{} image as PNG",
+                self.width, self.height
+            ));
+        }
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(png::BitDepth::Eight);
+            let error = |err: png::EncodingError| format!("Failed to encode PNG: {}", err);
+            let mut writer = encoder.write_header().map_err(error)?;
+            writer
+                .write_image_data(&self.pixels.concat())
+                .map_err(error)?;
+        }
+        Ok(out)
+    }
+
+    /// Read an 8-bit RGBA PNG file, as written by `to_png`.
+    pub fn from_png(data: &[u8]) -> Result<Self, String> {
+        let (info, mut reader) = png::Decoder::new(data)
+            .read_info()
+            .map_err(|err| format!("Invalid PNG: {}", err))?;
+        if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+            return Err(format!(
+                "Expected an 8-bit RGBA PNG, not {:?} {:?}",
+                info.bit_depth, info.color_type
+            ));
+        }
+        let mut buffer = vec![0; info.buffer_size()];
+        reader
+            .next_frame(&mut buffer)
+            .map_err(|err| format!("Invalid PNG: {}", err))?;
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels: buffer.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+        })
+    }
+
+    /// The color channels as a binary PPM file.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in &self.pixels {
+            out.extend_from_slice(&pixel[..3]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_gl_flips_rows() {
+        let data = [1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4];
+        let image = Image::from_gl(2, 2, &data);
+        assert_eq!(image.pixel(0, 0), [3; 4]);
+        assert_eq!(image.pixel(1, 1), [2; 4]);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut image = Image::new(3, 2, [10, 20, 30, 255]);
+        image.pixels[4] = [0, 0, 0, 0];
+        assert_eq!(Image::from_png(&image.to_png().unwrap()), Ok(image));
+    }
+
+    #[test]
+    fn empty_images() {
+        for &(width, height) in &[(0, 0), (0, 3), (3, 0)] {
+            let image = Image::<u8>::from_gl(width, height, &[]);
+            assert_eq!((image.width, image.height), (width, height));
+            assert!(image.pixels.is_empty());
+            assert!(image.to_png().is_err());
+        }
+        assert_eq!(
+            Image::<f32>::new(0, 0, [0.; 4]).to_pfm(),
+            b"PF\n0 0\n-1.0\n"
+        );
+    }
+}
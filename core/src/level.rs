@@ -0,0 +1,626 @@
+//! Loading and saving levels.
+//!
+//! A level is a `World`, including where the player spawns.
+//! There are two formats, which `World::load` tells apart by their first bytes.
+//! In both, blocks are listed in order of increasing `x`, then `y`, then `z`, then `w`;
+//! that is, with `x` varying fastest.
+//!
+//! # Text format, version 1
+//!
+//! ```text
+//! four-dimensions level 1
+//! min 0 0 0 0
+//! max 3 3 3 3
+//! spawn 1.5 1.5 1.5 1.5
+//! orientation 1 0 0 0
+//! vertical-angle 0
+//! palette
+//! . air
+//! * goal
+//! blocks
+//! ***
+//! ***
+//! ...
+//! ```
+//!
+//! - The first line gives the format version.
+//! - `min` and `max` are the extents of the world, as in `World::new`. They are required.
+//! - `spawn` is the player's starting position, `orientation` is their horizontal orientation
+//!   as a unit quaternion `w i j k`, and `vertical-angle` is how far up they are looking, in radians.
+//!   These are optional, and default as in `World::new`. They must be finite, and the orientation
+//!   must not be zero; it is normalized, so needn't have length exactly 1.
+//! - Each line of the `palette` assigns a character to a material, by name.
+//! - Each line after `blocks` is a row of blocks along the `x` axis, one character per block.
+//!   Blank lines are ignored, so they can be used to separate slices.
+//!
+//! # Binary format, version 1
+//!
+//! All numbers are little-endian.
+//!
+//! | Field          | Contents                                                            |
+//! |----------------|---------------------------------------------------------------------|
+//! | magic          | the bytes `4DLV`                                                    |
+//! | version        | `u32`                                                               |
+//! | min            | 4 × `i32`                                                           |
+//! | max            | 4 × `i32`                                                           |
+//! | spawn          | 4 × `f64`                                                           |
+//! | orientation    | 4 × `f64`, as `w i j k`                                             |
+//! | vertical angle | `f64`                                                               |
+//! | palette        | a `u8` count, then for each material a `u8` length and a UTF-8 name |
+//! | blocks         | runs of a `u32` length and a `u8` palette index, covering the world |
+
+use crate::world::{Block, World};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// The level played when no other is given.
+pub const DEFAULT_LEVEL: &[u8] = include_bytes!("../../resources/levels/default.txt");
+
+const TEXT_HEADER: &str = "four-dimensions level";
+const MAGIC: &[u8] = b"4DLV";
+const VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl World {
+    /// Load a level, in either format.
+    pub fn load(data: &[u8]) -> Result<Self, String> {
+        if data.starts_with(MAGIC) {
+            load_binary(&data[MAGIC.len()..])
+        } else {
+            let text = std::str::from_utf8(data)
+                .map_err(|err| format!("Level is neither binary nor UTF-8 text: {}", err))?;
+            load_text(text)
+        }
+    }
+
+    pub fn save(&self, format: Format) -> Result<Vec<u8>, String> {
+        match format {
+            Format::Text => Ok(save_text(self).into_bytes()),
+            Format::Binary => save_binary(self),
+        }
+    }
+}
+
+/// Every position in the world, in the order they are stored in a level.
+fn positions(min: [isize; 4], max: [isize; 4]) -> impl Iterator<Item = [isize; 4]> {
+    (min[3]..max[3]).flat_map(move |w| {
+        (min[2]..max[2]).flat_map(move |z| {
+            (min[1]..max[1]).flat_map(move |y| (min[0]..max[0]).map(move |x| [x, y, z, w]))
+        })
+    })
+}
+
+fn volume(min: [isize; 4], max: [isize; 4]) -> Result<usize, String> {
+    World::check_extents(min, max)?;
+    let mut out: usize = 1;
+    for i in 0..4 {
+        let width = max[i].checked_sub(min[i]).ok_or("Level is too large")?;
+        out = out
+            .checked_mul(width as usize)
+            .ok_or("Level is too large")?;
+    }
+    Ok(out)
+}
+
+fn spawn_position([x, y, z, w]: [f64; 4]) -> Result<na::Vector4<f64>, String> {
+    let position = na::Vector4::new(x, y, z, w);
+    if !position.iter().all(|x| x.is_finite()) {
+        return Err(format!(
+            "Spawn position {} {} {} {} is not finite",
+            x, y, z, w
+        ));
+    }
+    Ok(position)
+}
+
+/// The orientation is normalized, so it needn't have length 1, but it must have some length.
+fn spawn_orientation([w, i, j, k]: [f64; 4]) -> Result<na::UnitQuaternion<f64>, String> {
+    let q = na::Quaternion::new(w, i, j, k);
+    if !q.coords.iter().all(|x| x.is_finite()) || q.norm() == 0. {
+        return Err(format!(
+            "Orientation {} {} {} {} is not a finite, nonzero quaternion",
+            w, i, j, k
+        ));
+    }
+    Ok(na::UnitQuaternion::from_quaternion(q))
+}
+
+fn spawn_vertical_angle(angle: f64) -> Result<f64, String> {
+    if !angle.is_finite() {
+        return Err(format!("Vertical angle {} is not finite", angle));
+    }
+    Ok(angle)
+}
+
+fn symbol(block: Block) -> char {
+    match block {
+        Block::Air => '.',
+        Block::Stone => '#',
+        Block::Glass => '=',
+        Block::Goal => '*',
+        Block::Hazard => '!',
+    }
+}
+
+fn parse_4<T: FromStr + Default + Copy>(
+    key: &str,
+    words: std::str::SplitWhitespace,
+) -> Result<[T; 4], String> {
+    let mut out = [T::default(); 4];
+    let mut count = 0;
+    for word in words {
+        if count == 4 {
+            return Err(format!("Too many numbers after `{}`", key));
+        }
+        out[count] = word
+            .parse()
+            .map_err(|_| format!("Could not parse `{}` after `{}`", word, key))?;
+        count += 1;
+    }
+    if count < 4 {
+        return Err(format!("Expected four numbers after `{}`", key));
+    }
+    Ok(out)
+}
+
+fn load_text(text: &str) -> Result<World, String> {
+    let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+
+    let header = lines.next().unwrap_or("");
+    if !header.starts_with(TEXT_HEADER) {
+        return Err("Not a level file".into());
+    }
+    let version = &header[TEXT_HEADER.len()..];
+    if version.trim().parse::<u32>() != Ok(VERSION) {
+        return Err(format!("Unsupported level version `{}`", version.trim()));
+    }
+
+    let mut min = None;
+    let mut max = None;
+    let mut spawn = None;
+    let mut orientation = None;
+    let mut vertical_angle = None;
+    loop {
+        let line = lines.next().ok_or("Level has no palette")?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some("palette") => break,
+            Some(key @ "min") => min = Some(parse_4::<isize>(key, words)?),
+            Some(key @ "max") => max = Some(parse_4::<isize>(key, words)?),
+            Some(key @ "spawn") => spawn = Some(parse_4::<f64>(key, words)?),
+            Some(key @ "orientation") => orientation = Some(parse_4::<f64>(key, words)?),
+            Some("vertical-angle") => {
+                let word = words.next().unwrap_or("");
+                vertical_angle = Some(
+                    word.parse()
+                        .map_err(|_| format!("Could not parse vertical angle `{}`", word))?,
+                );
+            }
+            Some(key) => return Err(format!("Unknown key `{}`", key)),
+        }
+    }
+
+    let mut palette = HashMap::new();
+    loop {
+        let line = lines.next().ok_or("Level has no blocks")?;
+        if line.trim() == "blocks" {
+            break;
+        }
+        let mut chars = line.chars();
+        let symbol = match chars.next() {
+            None => continue,
+            Some(c) if c.is_whitespace() => {
+                return Err(format!(
+                    "Palette line `{}` should start with a symbol",
+                    line
+                ))
+            }
+            Some(c) => c,
+        };
+        let name = chars.as_str().trim();
+        let block = Block::from_name(name).ok_or_else(|| format!("Unknown material `{}`", name))?;
+        palette.insert(symbol, block);
+    }
+
+    let min = min.ok_or("Level has no `min`")?;
+    let max = max.ok_or("Level has no `max`")?;
+    let volume = volume(min, max)?;
+    let width = (max[0] - min[0]) as usize;
+
+    let mut symbols = Vec::new();
+    for row in lines.filter(|line| !line.trim().is_empty()) {
+        let len = symbols.len();
+        symbols.extend(row.chars());
+        if symbols.len() - len != width {
+            return Err(format!(
+                "Row `{}` has {} blocks, but the level is {} wide",
+                row,
+                symbols.len() - len,
+                width
+            ));
+        }
+    }
+    if symbols.len() != volume {
+        return Err(format!(
+            "Level has {} blocks, but should have {}",
+            symbols.len(),
+            volume
+        ));
+    }
+
+    let mut world = World::new(min, max);
+    for (pos, symbol) in positions(min, max).zip(symbols) {
+        let block = *palette
+            .get(&symbol)
+            .ok_or_else(|| format!("Symbol `{}` is not in the palette", symbol))?;
+        if block != Block::Air {
+            if let Some(b) = world.block_mut(pos) {
+                *b = block;
+            }
+        }
+    }
+
+    if let Some(spawn) = spawn {
+        world.spawn.position = spawn_position(spawn)?;
+    }
+    if let Some(orientation) = orientation {
+        world.spawn.horizontal_orientation = spawn_orientation(orientation)?;
+    }
+    if let Some(angle) = vertical_angle {
+        world.spawn.vertical_angle = spawn_vertical_angle(angle)?;
+    }
+
+    Ok(world)
+}
+
+fn save_text(world: &World) -> String {
+    let min = world.min();
+    let max = world.max();
+    let spawn = &world.spawn;
+    let q = spawn.horizontal_orientation;
+
+    let mut out = format!("{} {}\n", TEXT_HEADER, VERSION);
+    out.push_str(&format!(
+        "min {} {} {} {}\n",
+        min[0], min[1], min[2], min[3]
+    ));
+    out.push_str(&format!(
+        "max {} {} {} {}\n",
+        max[0], max[1], max[2], max[3]
+    ));
+    out.push_str(&format!(
+        "spawn {} {} {} {}\n",
+        spawn.position[0], spawn.position[1], spawn.position[2], spawn.position[3]
+    ));
+    out.push_str(&format!("orientation {} {} {} {}\n", q.w, q.i, q.j, q.k));
+    out.push_str(&format!("vertical-angle {}\n", spawn.vertical_angle));
+
+    out.push_str("palette\n");
+    for &block in &Block::ALL {
+        out.push_str(&format!("{} {}\n", symbol(block), block.material().name));
+    }
+
+    out.push_str("blocks\n");
+    for w in min[3]..max[3] {
+        for z in min[2]..max[2] {
+            for y in min[1]..max[1] {
+                for x in min[0]..max[0] {
+                    out.push(symbol(*world.block([x, y, z, w])));
+                }
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("Level ends unexpectedly".into());
+        }
+        let (out, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(f64::from_bits(u64::from_le_bytes(buf)))
+    }
+}
+
+fn load_binary(data: &[u8]) -> Result<World, String> {
+    let mut reader = Reader { data };
+
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(format!("Unsupported level version `{}`", version));
+    }
+
+    let mut min = [0; 4];
+    let mut max = [0; 4];
+    for x in min.iter_mut().chain(max.iter_mut()) {
+        *x = reader.i32()? as isize;
+    }
+    let volume = volume(min, max)?;
+
+    let mut world = World::new(min, max);
+    world.spawn.position =
+        spawn_position([reader.f64()?, reader.f64()?, reader.f64()?, reader.f64()?])?;
+    world.spawn.horizontal_orientation =
+        spawn_orientation([reader.f64()?, reader.f64()?, reader.f64()?, reader.f64()?])?;
+    world.spawn.vertical_angle = spawn_vertical_angle(reader.f64()?)?;
+
+    let mut palette = Vec::new();
+    for _ in 0..reader.u8()? {
+        let len = reader.u8()?;
+        let name = std::str::from_utf8(reader.bytes(len.into())?)
+            .map_err(|_| "Material name is not UTF-8")?;
+        palette.push(Block::from_name(name).ok_or_else(|| format!("Unknown material `{}`", name))?);
+    }
+
+    let mut positions = positions(min, max);
+    let mut remaining = volume;
+    while remaining > 0 {
+        let len = reader.u32()? as usize;
+        let index = reader.u8()?;
+        let block = *palette
+            .get(usize::from(index))
+            .ok_or_else(|| format!("Palette index {} is out of range", index))?;
+        if len > remaining {
+            return Err("Level has too many blocks".into());
+        }
+        remaining -= len;
+        for pos in positions.by_ref().take(len) {
+            if block != Block::Air {
+                if let Some(b) = world.block_mut(pos) {
+                    *b = block;
+                }
+            }
+        }
+    }
+
+    if !reader.data.is_empty() {
+        return Err("Level has trailing data".into());
+    }
+
+    Ok(world)
+}
+
+fn save_binary(world: &World) -> Result<Vec<u8>, String> {
+    let min = world.min();
+    let max = world.max();
+    let spawn = &world.spawn;
+    let q = spawn.horizontal_orientation;
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    for &x in min.iter().chain(&max) {
+        let x = i32::try_from(x).map_err(|_| format!("Extent {} is too large to save", x))?;
+        out.extend_from_slice(&x.to_le_bytes());
+    }
+    for &x in spawn
+        .position
+        .iter()
+        .chain(&[q.w, q.i, q.j, q.k, spawn.vertical_angle])
+    {
+        out.extend_from_slice(&x.to_bits().to_le_bytes());
+    }
+
+    out.push(Block::ALL.len() as u8);
+    for block in &Block::ALL {
+        let name = block.material().name;
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+    }
+
+    let mut run: Option<(Block, u32)> = None;
+    for pos in positions(min, max) {
+        let block = *world.block(pos);
+        run = match run {
+            Some((b, len)) if b == block && len < u32::MAX => Some((b, len + 1)),
+            Some((b, len)) => {
+                out.extend_from_slice(&len.to_le_bytes());
+                out.push(b.id() as u8);
+                Some((block, 1))
+            }
+            None => Some((block, 1)),
+        };
+    }
+    if let Some((b, len)) = run {
+        out.extend_from_slice(&len.to_le_bytes());
+        out.push(b.id() as u8);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> World {
+        let (min, max) = ([-2, -1, -3, 0], [1, 2, 0, 2]);
+        let mut world = World::new(min, max);
+        for (i, pos) in positions(min, max).enumerate() {
+            *world.block_mut(pos).unwrap() = Block::ALL[i * 7 % 11 % Block::ALL.len()];
+        }
+        world.spawn.position = na::Vector4::new(-0.5, 0.25, -1.75, 1.);
+        world.spawn.horizontal_orientation =
+            na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.7);
+        world.spawn.vertical_angle = -0.3;
+        world
+    }
+
+    fn assert_same(a: &World, b: &World) {
+        assert_eq!(a.min(), b.min());
+        assert_eq!(a.max(), b.max());
+        for pos in positions(a.min(), a.max()) {
+            assert_eq!(a.block(pos), b.block(pos), "at {:?}", pos);
+        }
+        assert_eq!(a.spawn.position, b.spawn.position);
+        let (p, q) = (
+            a.spawn.horizontal_orientation,
+            b.spawn.horizontal_orientation,
+        );
+        assert!((p.coords - q.coords).norm() < 1e-12);
+        assert_eq!(a.spawn.vertical_angle, b.spawn.vertical_angle);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let world = example();
+        let data = world.save(Format::Text).unwrap();
+        assert!(data.starts_with(TEXT_HEADER.as_bytes()));
+        assert_same(&World::load(&data).unwrap(), &world);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let world = example();
+        let data = world.save(Format::Binary).unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert_same(&World::load(&data).unwrap(), &world);
+    }
+
+    #[test]
+    fn text_palette() {
+        let text = "four-dimensions level 1\n\
+                    min -1 0 0 0\n\
+                    max 2 1 1 1\n\
+                    palette\n\
+                    x stone\n\
+                    o goal\n\
+                    _ air\n\
+                    blocks\n\
+                    xo_\n";
+        let world = World::load(text.as_bytes()).unwrap();
+        assert_eq!(*world.block([-1, 0, 0, 0]), Block::Stone);
+        assert_eq!(*world.block([0, 0, 0, 0]), Block::Goal);
+        assert_eq!(*world.block([1, 0, 0, 0]), Block::Air);
+        // Without `spawn`, the player spawns in the middle.
+        assert_eq!(world.spawn.position, na::Vector4::new(0.5, 0.5, 0.5, 0.5));
+    }
+
+    fn error(data: &[u8]) -> String {
+        World::load(data).err().expect("level should not load")
+    }
+
+    #[test]
+    fn bad_header() {
+        assert_eq!(error(b"four dimensions level 1\n"), "Not a level file");
+    }
+
+    #[test]
+    fn bad_version() {
+        assert_eq!(
+            error(b"four-dimensions level 2\n"),
+            "Unsupported level version `2`"
+        );
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        assert_eq!(error(&data), "Unsupported level version `2`");
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let data = example().save(Format::Binary).unwrap();
+        for len in &[MAGIC.len() + 2, MAGIC.len() + 20, data.len() - 1] {
+            assert_eq!(error(&data[..*len]), "Level ends unexpectedly");
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut data = example().save(Format::Binary).unwrap();
+        data.push(0);
+        assert_eq!(error(&data), "Level has trailing data");
+    }
+
+    #[test]
+    fn min_greater_than_max() {
+        let text = "four-dimensions level 1\nmin 0 2 0 0\nmax 1 1 1 1\npalette\nblocks\n";
+        assert_eq!(error(text.as_bytes()), "World has min 2 > max 1");
+    }
+
+    #[test]
+    fn extents_out_of_range() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        for &x in &[i32::MIN, 0, 0, 0, i32::MAX, 1, 1, 1] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        assert!(error(&data).starts_with("World extends from"));
+    }
+
+    #[test]
+    fn text_spawn_must_be_finite() {
+        for &(line, message) in &[
+            ("spawn 0 NaN 0 0", "Spawn position"),
+            ("spawn inf 0 0 0", "Spawn position"),
+            ("orientation 0 0 0 0", "Orientation"),
+            ("orientation 1 NaN 0 0", "Orientation"),
+            ("vertical-angle inf", "Vertical angle"),
+            ("vertical-angle NaN", "Vertical angle"),
+        ] {
+            let text = format!(
+                "four-dimensions level 1\nmin 0 0 0 0\nmax 1 1 1 1\n{}\npalette\n. air\nblocks\n.\n",
+                line
+            );
+            assert!(error(text.as_bytes()).starts_with(message), "{}", line);
+        }
+    }
+
+    #[test]
+    fn binary_spawn_must_be_finite() {
+        let data = example().save(Format::Binary).unwrap();
+        // The spawn position, orientation and vertical angle follow the magic, version and extents.
+        let start = MAGIC.len() + 4 + 8 * 4;
+        for &(field, values, message) in &[
+            (0, &[f64::NAN][..], "Spawn position"),
+            (3, &[f64::INFINITY][..], "Spawn position"),
+            (4, &[0., 0., 0., 0.][..], "Orientation"),
+            (6, &[f64::NAN][..], "Orientation"),
+            (8, &[f64::NEG_INFINITY][..], "Vertical angle"),
+        ] {
+            let mut data = data.clone();
+            for (n, x) in values.iter().enumerate() {
+                let at = start + 8 * (field + n);
+                data[at..at + 8].copy_from_slice(&x.to_bits().to_le_bytes());
+            }
+            assert!(error(&data).starts_with(message), "{:?}", values);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Everything about the game that doesn't depend on the platform it runs on:
+//! the world, its generation and file formats, physics, and the game loop.
+//!
+//! A frontend turns its events into [`Input`]s, calls [`Game::advance`] once per frame,
+//! and draws the [`Scene`].
+
+#![forbid(unsafe_code)]
+
+pub mod controls;
+pub mod fps;
+pub mod game;
+pub mod generate;
+pub mod image;
+pub mod level;
+mod mesh;
+pub mod phase;
+pub mod physics;
+pub mod projector;
+pub mod raster;
+pub mod raycast;
+pub mod rotation;
+pub mod render;
+pub mod timestep;
+pub mod touch;
+pub mod world;
+
+pub use game::{Game, Input, Scene};
@@ -0,0 +1,485 @@
+//! Turning a `World` into the `Mesh` that `render_4d` draws.
+//!
+//! The mesh is made of facets: one for each hyperplane between layers of cells,
+//! holding the faces that lie in it. Each face belongs to the chunk containing the cell just after it,
+//! along the axis it is perpendicular to. The faces are found chunk by chunk and cached,
+//! so that changing a block only means looking at the chunks it touches again,
+//! and rebuilding only the facets those chunks have faces in.
+//!
+//! Adjacent opaque faces in the same facet are merged into boxes before they are handed to `render_4d`,
+//! since each region costs it occlusion work.
+
+use crate::world::{chunk_of, Block, World, CHUNK_SIZE};
+use nalgebra as na;
+use render_4d::{Facet, Mesh, Texture};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// The axes of a facet: three within it, and then the one it is perpendicular to.
+/// Each is used twice, for the faces visible from either side; see `dimensions`.
+const ORIENTATIONS: [[usize; 4]; 4] = [[0, 1, 2, 3], [1, 0, 3, 2], [2, 3, 0, 1], [3, 2, 1, 0]];
+
+/// The axes of a texture polygon within a facet, as indices into the facet's first three axes.
+const TEXTURE_ORIENTATIONS: [[usize; 3]; 3] = [[0, 1, 2], [1, 2, 0], [2, 0, 1]];
+
+/// The axes of the facets with the given orientation, visible from the side given by `dir`.
+/// Swapping two axes flips which side is in front.
+fn dimensions(orientation: usize, dir: bool) -> [usize; 4] {
+    let mut dimensions = ORIENTATIONS[orientation];
+    if dir {
+        dimensions.swap(0, 1);
+    }
+    dimensions
+}
+
+/// Which facet a face lies in: its orientation, which side it is visible from,
+/// and its coordinate along the perpendicular axis.
+type FacetKey = (usize, bool, isize);
+
+/// Which texture polygon a face's outline is part of: its orientation, which side it faces,
+/// its coordinate along the perpendicular axis, and the id of the block.
+type TextureKey = (usize, bool, isize, usize);
+
+/// The faces within one facet, as the coordinates of their cells along the facet's axes.
+#[derive(Clone, Default, PartialEq, Debug)]
+struct Faces {
+    /// The opaque faces, which hide what is behind them.
+    regions: Vec<[isize; 3]>,
+    textures: BTreeMap<TextureKey, Vec<[isize; 2]>>,
+}
+
+/// The faces found so far, the facets built from them, and which chunks need looking at again.
+pub(crate) struct MeshCache {
+    /// The faces in each facet, split up by the chunk they belong to.
+    /// Kept in order, so that the mesh comes out the same however it was built.
+    faces: BTreeMap<FacetKey, BTreeMap<[isize; 4], Faces>>,
+    /// The facets built from `faces`, in the same order, with `keys` saying which is which.
+    mesh: Mesh,
+    keys: Vec<FacetKey>,
+    dirty: HashSet<[isize; 4]>,
+}
+
+impl Default for MeshCache {
+    fn default() -> Self {
+        Self {
+            faces: BTreeMap::new(),
+            mesh: Mesh { facets: Vec::new() },
+            keys: Vec::new(),
+            dirty: HashSet::new(),
+        }
+    }
+}
+
+impl Clone for MeshCache {
+    /// The copy keeps the faces, but builds its facets again, since they can't be copied.
+    fn clone(&self) -> Self {
+        let mut dirty = self.dirty.clone();
+        dirty.extend(
+            self.faces
+                .values()
+                .flat_map(|chunks| chunks.keys().copied()),
+        );
+        Self {
+            faces: self.faces.clone(),
+            mesh: Mesh { facets: Vec::new() },
+            keys: Vec::new(),
+            dirty,
+        }
+    }
+}
+
+impl MeshCache {
+    /// Note that the block at `pos` may have changed.
+    /// This affects the faces on each side of it, which may be in the next chunk along each axis.
+    pub(crate) fn invalidate(&mut self, pos: [isize; 4]) {
+        self.dirty.insert(chunk_of(pos));
+        for axis in 0..4 {
+            let mut next = pos;
+            next[axis] += 1;
+            self.dirty.insert(chunk_of(next));
+        }
+    }
+}
+
+impl World {
+    /// If there is a face between the cell `pos` and the cell before it along `axis`,
+    /// visible from the side given by `dir`, return the block it belongs to.
+    fn face(&self, mut pos: [isize; 4], axis: usize, dir: bool) -> Option<Block> {
+        let after = *self.block(pos);
+        pos[axis] -= 1;
+        let before = *self.block(pos);
+
+        let (front, back) = if dir {
+            (before, after)
+        } else {
+            (after, before)
+        };
+        if back != Block::Air && back != front && !front.is_opaque() {
+            Some(back)
+        } else {
+            None
+        }
+    }
+
+    /// The faces belonging to one chunk.
+    fn chunk_faces(&self, chunk: [isize; 4]) -> BTreeMap<FacetKey, Faces> {
+        let mut out: BTreeMap<FacetKey, Faces> = BTreeMap::new();
+
+        for orientation in 0..ORIENTATIONS.len() {
+            for &dir in &[false, true] {
+                let dimensions = dimensions(orientation, dir);
+
+                // The cells of this chunk that can have a face before them along `dimensions[3]`.
+                // This includes the layer just past the end of the world.
+                let mut lo = [0; 4];
+                let mut hi = [0; 4];
+                for i in 0..4 {
+                    let extra = isize::from(i == dimensions[3]);
+                    lo[i] = (chunk[i] * CHUNK_SIZE).max(self.min()[i]);
+                    hi[i] = ((chunk[i] + 1) * CHUNK_SIZE).min(self.max()[i] + extra);
+                }
+                let range = |i: usize| lo[dimensions[i]]..hi[dimensions[i]];
+
+                for i3 in range(3) {
+                    for i0 in range(0) {
+                        for i1 in range(1) {
+                            for i2 in range(2) {
+                                let mut pos = [0, 0, 0, 0];
+                                pos[dimensions[0]] = i0;
+                                pos[dimensions[1]] = i1;
+                                pos[dimensions[2]] = i2;
+                                pos[dimensions[3]] = i3;
+
+                                let block = match self.face(pos, dimensions[3], dir) {
+                                    Some(block) => block,
+                                    None => continue,
+                                };
+
+                                let faces = out.entry((orientation, dir, i3)).or_default();
+                                if block.is_opaque() {
+                                    faces.regions.push([i0, i1, i2]);
+                                }
+                                for (o2, dimensions2) in TEXTURE_ORIENTATIONS.iter().enumerate() {
+                                    for &dir2 in &[false, true] {
+                                        let j = |i: usize| pos[dimensions[dimensions2[i]]];
+                                        faces
+                                            .textures
+                                            .entry((o2, dir2, j(2), block.id()))
+                                            .or_default()
+                                            .push([j(0), j(1)]);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Look at each chunk that has changed since the last call again,
+    /// then rebuild the facets that any of them had or now have faces in.
+    fn update_mesh_cache(&mut self) {
+        let cache = &mut self.mesh_cache;
+        if cache.dirty.is_empty() {
+            return;
+        }
+        let dirty: Vec<[isize; 4]> = cache.dirty.drain().collect();
+
+        let mut stale = BTreeSet::new();
+        for &chunk in &dirty {
+            for (&key, chunks) in cache.faces.iter_mut() {
+                if chunks.remove(&chunk).is_some() {
+                    stale.insert(key);
+                }
+            }
+        }
+        for &chunk in &dirty {
+            for (key, faces) in self.chunk_faces(chunk) {
+                self.mesh_cache
+                    .faces
+                    .entry(key)
+                    .or_default()
+                    .insert(chunk, faces);
+                stale.insert(key);
+            }
+        }
+
+        let cache = &mut self.mesh_cache;
+        for key in stale {
+            let index = cache.keys.binary_search(&key);
+            let chunks = match cache.faces.get(&key) {
+                Some(chunks) if !chunks.is_empty() => chunks,
+                _ => {
+                    cache.faces.remove(&key);
+                    if let Ok(index) = index {
+                        cache.keys.remove(index);
+                        cache.mesh.facets.remove(index);
+                    }
+                    continue;
+                }
+            };
+
+            // Gather the faces of the facet from each chunk.
+            let mut gathered = Faces::default();
+            for faces in chunks.values() {
+                gathered.regions.extend_from_slice(&faces.regions);
+                for (&key, cells) in &faces.textures {
+                    gathered
+                        .textures
+                        .entry(key)
+                        .or_default()
+                        .extend_from_slice(cells);
+                }
+            }
+            let (orientation, dir, i3) = key;
+            let facet = facet(dimensions(orientation, dir), i3, gathered);
+
+            match index {
+                Ok(index) => cache.mesh.facets[index] = facet,
+                Err(index) => {
+                    cache.keys.insert(index, key);
+                    cache.mesh.facets.insert(index, facet);
+                }
+            }
+        }
+    }
+
+    /// The faces between blocks, in a form `render_4d` can project.
+    ///
+    /// Only the chunks changed since the last call are looked at again,
+    /// and only the facets they have faces in are rebuilt.
+    pub fn mesh(&mut self) -> &Mesh {
+        self.update_mesh_cache();
+        &self.mesh_cache.mesh
+    }
+}
+
+fn facet(dimensions: [usize; 4], i3: isize, faces: Faces) -> Facet {
+    let mut embedding = na::Matrix5x4::zeros();
+    embedding[(dimensions[0], 0)] = 1.;
+    embedding[(dimensions[1], 1)] = 1.;
+    embedding[(dimensions[2], 2)] = 1.;
+    embedding[(dimensions[3], 3)] = i3 as f64;
+    embedding[(4, 3)] = 1.;
+
+    let regions = merge_boxes(&faces.regions)
+        .into_iter()
+        .map(|(lo, hi)| {
+            vec![
+                na::RowVector4::new(1., 0., 0., -lo[0] as f64),
+                na::RowVector4::new(0., 1., 0., -lo[1] as f64),
+                na::RowVector4::new(0., 0., 1., -lo[2] as f64),
+                na::RowVector4::new(-1., 0., 0., hi[0] as f64),
+                na::RowVector4::new(0., -1., 0., hi[1] as f64),
+                na::RowVector4::new(0., 0., -1., hi[2] as f64),
+            ]
+        })
+        .collect();
+
+    let texture = faces
+        .textures
+        .into_iter()
+        .map(|((o2, dir2, j2, block), cells)| {
+            let dimensions2 = TEXTURE_ORIENTATIONS[o2];
+            let offset = Block::from_id(block).unwrap_or(Block::Air).texture_offset();
+
+            let mut embedding = na::Matrix4x3::zeros();
+            embedding[(dimensions2[0], 0)] = 1.;
+            embedding[(dimensions2[1], 1)] = 1.;
+            embedding[(dimensions2[1], 2)] = -offset;
+            embedding[(dimensions2[2], 2)] = j2 as f64 + if dir2 { 0.95 } else { 0.05 };
+            embedding[(3, 2)] = 1.;
+
+            let edge_loops = cells
+                .into_iter()
+                .map(|[j0, j1]| {
+                    let j1 = j1 as f64 + offset;
+                    vec![
+                        polygon3::Line::try_from_f64_array([1.0, 0.0, -j0 as f64 + 0.05]).unwrap(),
+                        polygon3::Line::try_from_f64_array([0.0, 1.0, -j1 + 0.05]).unwrap(),
+                        polygon3::Line::try_from_f64_array([-1.0, 0.0, j0 as f64 + 0.95]).unwrap(),
+                        polygon3::Line::try_from_f64_array([0.0, -1.0, j1 + 0.95]).unwrap(),
+                    ]
+                })
+                .collect();
+
+            let poly = polygon3::Polygon::try_from_edges(edge_loops).unwrap();
+            Texture { embedding, poly }
+        })
+        .collect();
+
+    Facet {
+        embedding,
+        regions,
+        texture,
+    }
+}
+
+/// Cover a set of unit cubes, given by their lowest corners, with boxes `lo[i] <= x[i] < hi[i]`.
+///
+/// This is greedy: starting from the lowest cube not yet covered, grow a box as far as it goes
+/// along the first axis, then the second, then the third. It isn't always the fewest boxes,
+/// but a flat wall becomes a single box.
+fn merge_boxes(cells: &[[isize; 3]]) -> Vec<([isize; 3], [isize; 3])> {
+    let mut remaining: BTreeSet<[isize; 3]> = cells.iter().copied().collect();
+    let mut boxes = Vec::new();
+
+    while let Some(&lo) = remaining.iter().next() {
+        let mut hi = [lo[0] + 1, lo[1] + 1, lo[2] + 1];
+        for axis in 0..3 {
+            loop {
+                // The layer of cells just past the box along `axis`.
+                let mut layer_lo = lo;
+                let mut layer_hi = hi;
+                layer_lo[axis] = hi[axis];
+                layer_hi[axis] = hi[axis] + 1;
+                if box_cells(layer_lo, layer_hi).all(|cell| remaining.contains(&cell)) {
+                    hi[axis] += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        for cell in box_cells(lo, hi) {
+            remaining.remove(&cell);
+        }
+        boxes.push((lo, hi));
+    }
+
+    boxes
+}
+
+fn box_cells(lo: [isize; 3], hi: [isize; 3]) -> impl Iterator<Item = [isize; 3]> {
+    (lo[0]..hi[0]).flat_map(move |i0| {
+        (lo[1]..hi[1]).flat_map(move |i1| (lo[2]..hi[2]).map(move |i2| [i0, i1, i2]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: [isize; 4] = [-1, -1, -1, -1];
+    const MAX: [isize; 4] = [10, 2, 10, 10];
+
+    fn positions() -> impl Iterator<Item = [isize; 4]> {
+        (MIN[0]..MAX[0]).flat_map(|x| {
+            (MIN[1]..MAX[1]).flat_map(move |y| {
+                (MIN[2]..MAX[2]).flat_map(move |z| (MIN[3]..MAX[3]).map(move |w| [x, y, z, w]))
+            })
+        })
+    }
+
+    /// A world with the same blocks, whose mesh is built in one go.
+    fn from_scratch(world: &World) -> World {
+        let mut out = World::new(MIN, MAX);
+        for pos in positions() {
+            if *world.block(pos) != Block::Air {
+                *out.block_mut(pos).unwrap() = *world.block(pos);
+            }
+        }
+        out
+    }
+
+    fn assert_same_mesh(a: &mut World, b: &mut World) {
+        let (a_mesh, b_mesh) = (a.mesh(), b.mesh());
+        assert_eq!(a_mesh.facets.len(), b_mesh.facets.len());
+        for (a, b) in a_mesh.facets.iter().zip(&b_mesh.facets) {
+            assert_eq!(a.embedding, b.embedding);
+            assert_eq!(a.regions, b.regions);
+            assert_eq!(a.texture.len(), b.texture.len());
+            for (a, b) in a.texture.iter().zip(&b.texture) {
+                assert_eq!(a.embedding, b.embedding);
+            }
+        }
+        assert_eq!(a.mesh_cache.keys, b.mesh_cache.keys);
+        assert_eq!(a.mesh_cache.faces, b.mesh_cache.faces);
+    }
+
+    #[test]
+    fn incremental_mesh_matches_a_fresh_one() {
+        let mut world = World::new(MIN, MAX);
+        for pos in positions().filter(|pos| pos[1] < 0) {
+            *world.block_mut(pos).unwrap() = Block::Stone;
+        }
+        world.mesh();
+
+        // Each batch touches cells on either side of a chunk boundary, or the edge of the world.
+        let batches: &[&[([isize; 4], Block)]] = &[
+            &[([7, 0, 7, 7], Block::Stone), ([8, 0, 8, 8], Block::Stone)],
+            &[([-1, 0, 0, 0], Block::Glass), ([0, 0, -1, 7], Block::Goal)],
+            &[([0, -1, 0, 0], Block::Air), ([7, -1, 8, -1], Block::Air)],
+            &[([7, 0, 7, 7], Block::Air), ([7, 1, 7, 7], Block::Hazard)],
+            &[([9, 1, 9, 9], Block::Stone), ([-1, -1, -1, -1], Block::Air)],
+            &[([8, 0, 8, 8], Block::Glass), ([-1, 0, 0, 0], Block::Air)],
+        ];
+        for batch in batches {
+            for &(pos, block) in batch.iter() {
+                *world.block_mut(pos).unwrap() = block;
+            }
+            let mut fresh = from_scratch(&world);
+            assert_same_mesh(&mut world, &mut fresh);
+            let mut copy = world.clone();
+            assert_same_mesh(&mut world, &mut copy);
+        }
+
+        // Clearing the world leaves no facets behind.
+        for pos in positions() {
+            if *world.block(pos) != Block::Air {
+                *world.block_mut(pos).unwrap() = Block::Air;
+            }
+        }
+        assert!(world.mesh().facets.is_empty());
+        assert!(world.mesh_cache.faces.is_empty());
+    }
+
+    /// Check that the boxes cover exactly the cells, without overlapping, and return how many there are.
+    fn merged(cells: &[[isize; 3]]) -> usize {
+        let boxes = merge_boxes(cells);
+        let mut covered = Vec::new();
+        for &(lo, hi) in &boxes {
+            assert!((0..3).all(|i| lo[i] < hi[i]), "empty box {:?}", (lo, hi));
+            covered.extend(box_cells(lo, hi));
+        }
+        let count = covered.len();
+        covered.sort();
+        covered.dedup();
+        assert_eq!(covered.len(), count, "boxes overlap: {:?}", boxes);
+        let mut expected = cells.to_vec();
+        expected.sort();
+        assert_eq!(covered, expected);
+        boxes.len()
+    }
+
+    #[test]
+    fn merge_a_single_cell() {
+        assert_eq!(merge_boxes(&[[3, -2, 5]]), vec![([3, -2, 5], [4, -1, 6])]);
+        assert_eq!(merged(&[]), 0);
+    }
+
+    #[test]
+    fn merge_an_l_shape() {
+        let cells: Vec<[isize; 3]> = (0..4)
+            .map(|i| [i, 0, 0])
+            .chain((1..3).map(|j| [0, j, 0]))
+            .collect();
+        assert_eq!(merged(&cells), 2);
+    }
+
+    #[test]
+    fn merge_a_hollow_square() {
+        let cells: Vec<[isize; 3]> = box_cells([0, 0, 0], [4, 4, 1])
+            .filter(|&[i, j, _]| i == 0 || i == 3 || j == 0 || j == 3)
+            .collect();
+        assert_eq!(merged(&cells), 4);
+    }
+
+    #[test]
+    fn merge_a_full_rectangle() {
+        let cells: Vec<[isize; 3]> = box_cells([-2, 1, 0], [3, 4, 2]).collect();
+        assert_eq!(merged(&cells), 1);
+        assert_eq!(merge_boxes(&cells), vec![([-2, 1, 0], [3, 4, 2])]);
+    }
+}
@@ -0,0 +1,106 @@
+//! The phases of a game, from the title screen to the win screen, and the events that move between them.
+
+/// What the game is doing. Only while `Playing` does time pass, or input do anything.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Phase {
+    /// Before the player has started. The world is shown, but nothing happens.
+    Title,
+    Playing,
+    /// The player has stopped for now, e.g. by releasing the mouse.
+    Paused,
+    /// Every tesseract has been eaten.
+    Won,
+    /// The world has been rebuilt, and is waiting for its first picture before play starts again.
+    Restarting,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    Start,
+    Pause,
+    Resume,
+    Win,
+    Restart,
+    /// The rebuilt world is ready to be shown.
+    Ready,
+}
+
+impl Phase {
+    /// The phase after `event`. Events that don't make sense in this phase are ignored.
+    pub fn next(self, event: Event) -> Self {
+        match (self, event) {
+            (Phase::Title, Event::Start) | (Phase::Paused, Event::Resume) => Phase::Playing,
+            (Phase::Playing, Event::Pause) => Phase::Paused,
+            (Phase::Playing, Event::Win) => Phase::Won,
+            (Phase::Paused, Event::Restart) | (Phase::Won, Event::Restart) => Phase::Restarting,
+            (Phase::Restarting, Event::Ready) => Phase::Playing,
+            (phase, _) => phase,
+        }
+    }
+
+    /// A heading to show over the game, if it isn't being played.
+    pub fn heading(self) -> Option<&'static str> {
+        match self {
+            Phase::Title => Some("four-dimensions"),
+            Phase::Playing => None,
+            Phase::Paused => Some("Paused"),
+            Phase::Won => Some("You ate every tesseract!"),
+            Phase::Restarting => Some("Restarting..."),
+        }
+    }
+
+    /// Whether the game can be restarted from this phase.
+    pub fn can_restart(self) -> bool {
+        self != Phase::Restarting && self.next(Event::Restart) == Phase::Restarting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHASES: [Phase; 5] = [
+        Phase::Title,
+        Phase::Playing,
+        Phase::Paused,
+        Phase::Won,
+        Phase::Restarting,
+    ];
+    const EVENTS: [Event; 6] = [
+        Event::Start,
+        Event::Pause,
+        Event::Resume,
+        Event::Win,
+        Event::Restart,
+        Event::Ready,
+    ];
+
+    #[test]
+    fn transitions() {
+        let moves = [
+            (Phase::Title, Event::Start, Phase::Playing),
+            (Phase::Playing, Event::Pause, Phase::Paused),
+            (Phase::Playing, Event::Win, Phase::Won),
+            (Phase::Paused, Event::Resume, Phase::Playing),
+            (Phase::Paused, Event::Restart, Phase::Restarting),
+            (Phase::Won, Event::Restart, Phase::Restarting),
+            (Phase::Restarting, Event::Ready, Phase::Playing),
+        ];
+        for &phase in &PHASES {
+            for &event in &EVENTS {
+                // Every other event is ignored.
+                let expected = moves
+                    .iter()
+                    .find(|&&(from, on, _)| (from, on) == (phase, event))
+                    .map_or(phase, |&(_, _, to)| to);
+                assert_eq!(phase.next(event), expected, "{:?} on {:?}", phase, event);
+            }
+        }
+    }
+
+    #[test]
+    fn restarting() {
+        let restartable: Vec<Phase> = PHASES.iter().copied().filter(|p| p.can_restart()).collect();
+        assert_eq!(restartable, [Phase::Paused, Phase::Won]);
+    }
+}
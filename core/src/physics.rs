@@ -0,0 +1,253 @@
+//! How the player moves through the world: collision, gravity and jumping.
+
+use crate::world::World;
+use nalgebra as na;
+
+/// Keeps boxes that are exactly touching a block from counting as overlapping it.
+const EPSILON: f64 = 1e-9;
+
+/// An axis-aligned box, given relative to a position.
+#[derive(Copy, Clone, Debug)]
+pub struct Collider {
+    pub min: na::Vector4<f64>,
+    pub max: na::Vector4<f64>,
+}
+
+impl Collider {
+    /// The range of cells, inclusive, that the box overlaps when at `position`.
+    fn cell_range(&self, position: na::Vector4<f64>) -> ([isize; 4], [isize; 4]) {
+        let mut lo = [0; 4];
+        let mut hi = [0; 4];
+        for i in 0..4 {
+            lo[i] = (position[i] + self.min[i] + EPSILON).floor() as isize;
+            hi[i] = (position[i] + self.max[i] - EPSILON).floor() as isize;
+        }
+        (lo, hi)
+    }
+
+    /// Every cell that the box overlaps when at `position`.
+    pub fn cells(&self, position: na::Vector4<f64>) -> impl Iterator<Item = [isize; 4]> {
+        let (lo, hi) = self.cell_range(position);
+        cells(lo, hi)
+    }
+
+    /// Move the box, starting at `position`, by `displacement`.
+    /// Movement is stopped by solid blocks, but the box slides along them.
+    ///
+    /// Returns whether movement was blocked along each axis.
+    pub fn move_and_slide(
+        &self,
+        world: &World,
+        position: &mut na::Vector4<f64>,
+        displacement: na::Vector4<f64>,
+    ) -> [bool; 4] {
+        let mut blocked = [false; 4];
+        // Vertical first, so that walking along the floor isn't blocked by the floor.
+        for &axis in &[1, 0, 2, 3] {
+            blocked[axis] = self.sweep(world, position, axis, displacement[axis]);
+        }
+        blocked
+    }
+
+    /// Move the box along a single axis.
+    fn sweep(
+        &self,
+        world: &World,
+        position: &mut na::Vector4<f64>,
+        axis: usize,
+        distance: f64,
+    ) -> bool {
+        let (mut lo, mut hi) = self.cell_range(*position);
+
+        if distance > 0. {
+            let start = hi[axis] + 1;
+            let end = (position[axis] + self.max[axis] + distance - EPSILON).floor() as isize;
+            for layer in start..=end {
+                lo[axis] = layer;
+                hi[axis] = layer;
+                if cells(lo, hi).any(|pos| world.block(pos).material().solid) {
+                    position[axis] = layer as f64 - self.max[axis];
+                    return true;
+                }
+            }
+        } else if distance < 0. {
+            let start = lo[axis] - 1;
+            let end = (position[axis] + self.min[axis] + distance + EPSILON).floor() as isize;
+            for layer in (end..=start).rev() {
+                lo[axis] = layer;
+                hi[axis] = layer;
+                if cells(lo, hi).any(|pos| world.block(pos).material().solid) {
+                    position[axis] = (layer + 1) as f64 - self.min[axis];
+                    return true;
+                }
+            }
+        }
+
+        position[axis] += distance;
+        false
+    }
+}
+
+/// Every cell in the range from `lo` to `hi`, inclusive.
+fn cells(lo: [isize; 4], hi: [isize; 4]) -> impl Iterator<Item = [isize; 4]> {
+    (lo[0]..=hi[0]).flat_map(move |x| {
+        (lo[1]..=hi[1]).flat_map(move |y| {
+            (lo[2]..=hi[2]).flat_map(move |z| (lo[3]..=hi[3]).map(move |w| [x, y, z, w]))
+        })
+    })
+}
+
+/// How the player moves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Movement {
+    /// Walk along the ground, falling under gravity.
+    Walk,
+    /// Fly in any direction, colliding with solid blocks.
+    Fly,
+    /// Fly in any direction, passing through blocks.
+    Noclip,
+}
+
+impl Movement {
+    pub fn next(self) -> Self {
+        match self {
+            Movement::Walk => Movement::Fly,
+            Movement::Fly => Movement::Noclip,
+            Movement::Noclip => Movement::Walk,
+        }
+    }
+}
+
+/// Acceleration due to gravity, along -y, in blocks per second squared.
+pub const GRAVITY: f64 = 20.;
+/// The vertical velocity given by a jump. This jumps a little over one block high.
+pub const JUMP_SPEED: f64 = 7.;
+/// The top walking speed, in blocks per second.
+pub const WALK_SPEED: f64 = 2.;
+/// How fast the player speeds up and slows down while on the ground.
+pub const GROUND_ACCELERATION: f64 = 20.;
+/// How fast the player speeds up and slows down while in the air.
+pub const AIR_ACCELERATION: f64 = 4.;
+
+/// Move `current` towards `target`, changing it by at most `max_change`.
+pub fn approach(
+    current: na::Vector4<f64>,
+    target: na::Vector4<f64>,
+    max_change: f64,
+) -> na::Vector4<f64> {
+    let difference = target - current;
+    let distance = difference.norm();
+    if distance <= max_change {
+        target
+    } else {
+        current + difference * (max_change / distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Block;
+
+    /// The player's collider.
+    fn player() -> Collider {
+        Collider {
+            min: na::Vector4::new(-0.2, -0.4, -0.2, -0.2),
+            max: na::Vector4::new(0.2, 0.2, 0.2, 0.2),
+        }
+    }
+
+    /// A world with stone wherever `solid` says.
+    fn world(solid: impl Fn([isize; 4]) -> bool) -> World {
+        let mut world = World::new([0; 4], [8; 4]);
+        for pos in cells([0; 4], [7; 4]) {
+            if solid(pos) {
+                *world.block_mut(pos).unwrap() = Block::Stone;
+            }
+        }
+        world
+    }
+
+    fn assert_near(a: na::Vector4<f64>, b: na::Vector4<f64>) {
+        assert!((a - b).norm() < 1e-9, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn stops_flush_against_a_wall() {
+        let world = world(|pos| pos[0] == 4);
+        let mut position = na::Vector4::repeat(2.5);
+        let blocked =
+            player().move_and_slide(&world, &mut position, na::Vector4::new(3., 0., 0., 0.));
+        assert_eq!(blocked, [true, false, false, false]);
+        assert_near(position, na::Vector4::new(3.8, 2.5, 2.5, 2.5));
+
+        // Touching the wall doesn't stop moving along it.
+        let blocked =
+            player().move_and_slide(&world, &mut position, na::Vector4::new(0., 0., 1., 0.));
+        assert_eq!(blocked, [false; 4]);
+        assert_near(position, na::Vector4::new(3.8, 2.5, 3.5, 2.5));
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        let world = world(|pos| pos[0] == 4);
+        let mut position = na::Vector4::repeat(2.5);
+        let displacement = na::Vector4::new(2., 0., 1., -0.5);
+        let blocked = player().move_and_slide(&world, &mut position, displacement);
+        assert_eq!(blocked, [true, false, false, false]);
+        assert_near(position, na::Vector4::new(3.8, 2.5, 3.5, 2.));
+    }
+
+    #[test]
+    fn does_not_tunnel_through_a_thin_wall() {
+        let world = world(|pos| pos[3] == 5);
+        for &distance in &[2.5, 10., 1000.] {
+            let mut position = na::Vector4::repeat(2.5);
+            let blocked = player().move_and_slide(
+                &world,
+                &mut position,
+                na::Vector4::new(0., 0., 0., distance),
+            );
+            assert_eq!(blocked, [false, false, false, true]);
+            assert_near(position, na::Vector4::new(2.5, 2.5, 2.5, 4.8));
+        }
+    }
+
+    #[test]
+    fn lands_on_the_ground() {
+        let world = world(|pos| pos[1] == 0);
+        let mut position = na::Vector4::new(2.5, 5., 2.5, 2.5);
+        let blocked =
+            player().move_and_slide(&world, &mut position, na::Vector4::new(0.5, -20., 0., 0.));
+        assert_eq!(blocked, [false, true, false, false]);
+        assert_near(position, na::Vector4::new(3., 1.4, 2.5, 2.5));
+    }
+
+    #[test]
+    fn approach_never_overshoots() {
+        let target = na::Vector4::new(1., -2., 0.5, 3.);
+        let mut current = na::Vector4::zeros();
+        let mut previous_distance = (target - current).norm();
+        for _ in 0..100 {
+            current = approach(current, target, 0.7);
+            let distance = (target - current).norm();
+            assert!(distance <= previous_distance);
+            // Still heading straight for the target.
+            assert!(
+                distance < 1e-9
+                    || (target - current).normalize().dot(&target.normalize()) > 1. - 1e-9
+            );
+            previous_distance = distance;
+        }
+        assert_eq!(current, target);
+        assert_eq!(approach(target, target, 0.7), target);
+        assert_near(
+            approach(
+                na::Vector4::zeros(),
+                na::Vector4::new(0., 0., 0., 10.),
+                0.25,
+            ),
+            na::Vector4::new(0., 0., 0., 0.25),
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Finding the visible triangles, which is the slowest part of drawing a frame.
+//!
+//! `Game` hands this work to a `Projector`, which keeps its own copy of the world and may work on
+//! another thread. Until newer triangles arrive, the game keeps drawing the most recent ones it has.
+//!
+//! A frontend whose threads can't share memory, like a Web Worker, can send `Request`s and triangles
+//! as arrays of numbers, using `Request::encode` and `encode_triangles`.
+
+use crate::world::{Block, World};
+use nalgebra as na;
+use render_4d::Triangle;
+
+pub trait Projector {
+    /// Change a block in the projector's copy of the world.
+    fn set_block(&mut self, pos: [isize; 4], block: Block);
+    /// Start finding the triangles visible through `projection`.
+    fn request(&mut self, projection: na::Matrix5<f64>);
+    /// The triangles from the most recent finished request, if they haven't been returned already.
+    fn poll(&mut self) -> Option<Vec<Triangle>>;
+}
+
+/// Something for a projector to do.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Request {
+    SetBlock([isize; 4], Block),
+    Project(na::Matrix5<f64>),
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<f64> {
+        match *self {
+            Request::SetBlock([x, y, z, w], block) => vec![
+                0.,
+                x as f64,
+                y as f64,
+                z as f64,
+                w as f64,
+                block.id() as f64,
+            ],
+            Request::Project(projection) => {
+                let mut out = vec![1.];
+                out.extend_from_slice(projection.as_slice());
+                out
+            }
+        }
+    }
+
+    pub fn decode(data: &[f64]) -> Result<Self, String> {
+        match data {
+            [tag, x, y, z, w, id] if *tag == 0. => {
+                let block = Block::from_id(*id as usize)
+                    .ok_or_else(|| format!("No block has the id {}", id))?;
+                Ok(Request::SetBlock(
+                    [*x as isize, *y as isize, *z as isize, *w as isize],
+                    block,
+                ))
+            }
+            [tag, projection @ ..] if *tag == 1. && projection.len() == 25 => {
+                Ok(Request::Project(na::Matrix5::from_column_slice(projection)))
+            }
+            _ => Err(format!("Invalid request of length {}", data.len())),
+        }
+    }
+}
+
+/// Each vertex has a position and a texture coordinate, and each triangle says whether it is negated.
+const FLOATS_PER_TRIANGLE: usize = 3 * (4 + 3) + 1;
+
+pub fn encode_triangles(triangles: &[Triangle]) -> Vec<f64> {
+    let mut out = Vec::with_capacity(triangles.len() * FLOATS_PER_TRIANGLE);
+    for triangle in triangles {
+        for vertex in &triangle.vertices {
+            out.extend_from_slice(&vertex.position);
+            out.extend_from_slice(&vertex.texcoord);
+        }
+        out.push(if triangle.negated { 1. } else { 0. });
+    }
+    out
+}
+
+pub fn decode_triangles(data: &[f64]) -> Result<Vec<Triangle>, String> {
+    let chunks = data.chunks_exact(FLOATS_PER_TRIANGLE);
+    if !chunks.remainder().is_empty() {
+        return Err(format!(
+            "{} numbers don't make a whole number of triangles",
+            data.len()
+        ));
+    }
+    Ok(chunks
+        .map(|t| {
+            let vertex = |i: usize| {
+                let v = &t[i * 7..];
+                render_4d::Vertex {
+                    position: [v[0], v[1], v[2], v[3]],
+                    texcoord: [v[4], v[5], v[6]],
+                }
+            };
+            Triangle {
+                vertices: [vertex(0), vertex(1), vertex(2)],
+                negated: t[FLOATS_PER_TRIANGLE - 1] != 0.,
+            }
+        })
+        .collect())
+}
+
+/// Does the work as soon as it is requested, on the calling thread.
+pub struct Synchronous {
+    /// This keeps its mesh up to date as blocks change.
+    world: World,
+    result: Option<Vec<Triangle>>,
+}
+
+impl Synchronous {
+    pub fn new(world: World) -> Self {
+        Self {
+            world,
+            result: None,
+        }
+    }
+
+    pub fn project(&mut self, projection: na::Matrix5<f64>) -> Vec<Triangle> {
+        self.world.mesh().project(projection).collect()
+    }
+
+    /// Do what a request says, returning the triangles if it asked for them.
+    pub fn handle(&mut self, request: Request) -> Option<Vec<Triangle>> {
+        match request {
+            Request::SetBlock(pos, block) => {
+                self.set_block(pos, block);
+                None
+            }
+            Request::Project(projection) => Some(self.project(projection)),
+        }
+    }
+}
+
+impl Projector for Synchronous {
+    fn set_block(&mut self, pos: [isize; 4], block: Block) {
+        if let Some(b) = self.world.block_mut(pos) {
+            *b = block;
+        }
+    }
+
+    fn request(&mut self, projection: na::Matrix5<f64>) {
+        self.result = Some(self.project(projection));
+    }
+
+    fn poll(&mut self) -> Option<Vec<Triangle>> {
+        self.result.take()
+    }
+}
+
+/// Does the work on a thread of its own, which stops when this is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Threaded {
+    requests: std::sync::mpsc::Sender<Request>,
+    results: std::sync::mpsc::Receiver<Vec<Triangle>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Threaded {
+    pub fn new(world: World) -> Self {
+        let (requests, receiver) = std::sync::mpsc::channel::<Request>();
+        let (sender, results) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut projector = Synchronous::new(world);
+            while let Ok(request) = receiver.recv() {
+                // Catch up on everything that has been asked for, but only project once.
+                let mut projection = None;
+                for request in std::iter::once(request).chain(receiver.try_iter()) {
+                    match request {
+                        Request::SetBlock(pos, block) => projector.set_block(pos, block),
+                        Request::Project(p) => projection = Some(p),
+                    }
+                }
+                if let Some(projection) = projection {
+                    if sender.send(projector.project(projection)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self { requests, results }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Projector for Threaded {
+    fn set_block(&mut self, pos: [isize; 4], block: Block) {
+        // This only fails if the thread has panicked, which it will have reported.
+        let _ = self.requests.send(Request::SetBlock(pos, block));
+    }
+
+    fn request(&mut self, projection: na::Matrix5<f64>) {
+        let _ = self.requests.send(Request::Project(projection));
+    }
+
+    fn poll(&mut self) -> Option<Vec<Triangle>> {
+        self.results.try_iter().last()
+    }
+}
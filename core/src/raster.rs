@@ -0,0 +1,254 @@
+//! A software version of the renderers' two-pass pipeline (see `render`),
+//! for drawing without a GPU.
+//!
+//! It follows OpenGL's rules closely enough that it can stand in for the GPU in tests:
+//! triangles are clipped to `-w <= z <= w`, attributes are interpolated perspective-correctly,
+//! pixels are sampled at their centers, edges shared by two triangles are drawn only once,
+//! and textures are sampled with `NEAREST` filtering and `REPEAT` wrapping.
+
+use crate::image::Image;
+use crate::render::{self, TEXTURE_SIZE};
+use nalgebra as na;
+
+pub struct Rasterizer {
+    textures: Vec<u8>,
+}
+
+impl Default for Rasterizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rasterizer {
+    pub fn new() -> Self {
+        Self {
+            textures: render::textures(),
+        }
+    }
+
+    /// Both passes: the picture the player would see.
+    pub fn render(
+        &self,
+        triangles: &[render_4d::Triangle],
+        screen: na::Matrix4<f64>,
+        width: usize,
+        height: usize,
+    ) -> Image<u8> {
+        self.accumulate(triangles, screen, width, height).tone_map()
+    }
+
+    /// The first pass: add up the light absorbed by each triangle, as seen through `screen`.
+    ///
+    /// The result is the same as the float buffer the GPU renders into, before tone mapping.
+    pub fn accumulate(
+        &self,
+        triangles: &[render_4d::Triangle],
+        screen: na::Matrix4<f64>,
+        width: usize,
+        height: usize,
+    ) -> Image<f32> {
+        let mut image = Image::new(width, height, [0., 0., 0., 1.]);
+
+        let vertices: Vec<render::Vertex> = render::vertices(triangles).collect();
+        for triangle in vertices.chunks(3) {
+            let polygon: Vec<Corner> = triangle
+                .iter()
+                .map(|vertex| Corner::new(screen, vertex))
+                .collect();
+            let polygon = clip(polygon);
+            // A triangle fan. The layer is flat, taken from the provoking (last) vertex.
+            for i in 2..polygon.len() {
+                self.fill(
+                    &mut image,
+                    [&polygon[0], &polygon[i - 1], &polygon[i]],
+                    triangle[2].layer,
+                );
+            }
+        }
+
+        image
+    }
+
+    fn fill(&self, image: &mut Image<f32>, corners: [&Corner; 3], layer: f32) {
+        let (width, height) = (image.width as f64, image.height as f64);
+
+        // Window coordinates, with y increasing upward as in OpenGL.
+        let mut points = [[0.; 2]; 3];
+        for (point, corner) in points.iter_mut().zip(&corners) {
+            let [x, y, _, w] = corner.clip;
+            *point = [(x / w + 1.) * 0.5 * width, (y / w + 1.) * 0.5 * height];
+        }
+
+        let area = edge(points[0], points[1], points[2]);
+        if area == 0. || !area.is_finite() {
+            return;
+        }
+        // Visit the corners counterclockwise, so that the inside is to the left of each edge.
+        let order = if area > 0. { [0, 1, 2] } else { [0, 2, 1] };
+        let area = area.abs();
+        let p = [points[order[0]], points[order[1]], points[order[2]]];
+        let c = [corners[order[0]], corners[order[1]], corners[order[2]]];
+
+        let min_x = p.iter().map(|p| p[0]).fold(f64::INFINITY, f64::min);
+        let max_x = p.iter().map(|p| p[0]).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = p.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+        let max_y = p.iter().map(|p| p[1]).fold(f64::NEG_INFINITY, f64::max);
+        let x0 = (min_x - 0.5).ceil().max(0.) as usize;
+        let x1 = ((max_x - 0.5).floor() + 1.).max(0.).min(width) as usize;
+        let y0 = (min_y - 0.5).ceil().max(0.) as usize;
+        let y1 = ((max_y - 0.5).floor() + 1.).max(0.).min(height) as usize;
+
+        let top_left = [
+            is_top_left(p[1], p[2]),
+            is_top_left(p[2], p[0]),
+            is_top_left(p[0], p[1]),
+        ];
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let center = [x as f64 + 0.5, y as f64 + 0.5];
+                let weights = [
+                    edge(p[1], p[2], center),
+                    edge(p[2], p[0], center),
+                    edge(p[0], p[1], center),
+                ];
+                let inside = weights
+                    .iter()
+                    .zip(&top_left)
+                    .all(|(&e, &top_left)| e > 0. || (e == 0. && top_left));
+                if !inside {
+                    continue;
+                }
+
+                // Perspective-correct interpolation.
+                let mut varyings = [0.; VARYINGS];
+                let mut total = 0.;
+                for (corner, &weight) in c.iter().zip(&weights) {
+                    let weight = weight / area / corner.clip[3];
+                    total += weight;
+                    for (v, &a) in varyings.iter_mut().zip(&corner.varyings) {
+                        *v += weight * a;
+                    }
+                }
+                for v in varyings.iter_mut() {
+                    *v /= total;
+                }
+
+                let [s, t, q, sign, r, g, b, opacity] = varyings;
+                let texel = self.texel(s / q, t / q, layer);
+                let color = [r, g, b];
+                let pixel = &mut image.pixels[(image.height - 1 - y) * image.width + x];
+                for i in 0..3 {
+                    pixel[i] += ((1. - texel[i] * color[i]) * opacity * 0.5 * sign) as f32;
+                }
+                pixel[3] += 1.;
+            }
+        }
+    }
+
+    /// Sample the textures, with nearest-neighbor filtering and repeating.
+    fn texel(&self, s: f64, t: f64, layer: f32) -> [f64; 3] {
+        let wrap = |x: f64| {
+            if x.is_finite() {
+                ((x - x.floor()) * TEXTURE_SIZE as f64) as usize % TEXTURE_SIZE
+            } else {
+                0
+            }
+        };
+        let layer = (layer.round().max(0.) as usize).min(render::TEXTURE_COUNT - 1);
+        let index = ((layer * TEXTURE_SIZE + wrap(t)) * TEXTURE_SIZE + wrap(s)) * 4;
+        let texel = &self.textures[index..index + 3];
+        [
+            f64::from(texel[0]) / 255.,
+            f64::from(texel[1]) / 255.,
+            f64::from(texel[2]) / 255.,
+        ]
+    }
+}
+
+/// texcoord (3), sign, color (3), opacity.
+const VARYINGS: usize = 8;
+
+/// A corner of a triangle in clip space, after the vertex shader.
+#[derive(Copy, Clone, Debug)]
+struct Corner {
+    clip: [f64; 4],
+    varyings: [f64; VARYINGS],
+}
+
+impl Corner {
+    fn new(screen: na::Matrix4<f64>, vertex: &render::Vertex) -> Self {
+        let p = vertex.position;
+        let clip = screen
+            * na::Vector4::new(
+                f64::from(p[0]),
+                f64::from(p[1]),
+                f64::from(p[2]),
+                f64::from(p[3]),
+            );
+        let [s, t, q] = vertex.texcoord;
+        let [r, g, b] = vertex.color;
+        Self {
+            clip: [clip[0], clip[1], clip[2], clip[3]],
+            varyings: [
+                f64::from(s),
+                f64::from(t),
+                f64::from(q),
+                f64::from(vertex.sign),
+                f64::from(r),
+                f64::from(g),
+                f64::from(b),
+                f64::from(vertex.opacity),
+            ],
+        }
+    }
+
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let mut out = *self;
+        for (a, &b) in out.clip.iter_mut().zip(&other.clip) {
+            *a += (b - *a) * t;
+        }
+        for (a, &b) in out.varyings.iter_mut().zip(&other.varyings) {
+            *a += (b - *a) * t;
+        }
+        out
+    }
+}
+
+/// The smallest w that a clipped vertex can have, to keep the perspective divide finite.
+const MIN_W: f64 = 1e-9;
+
+/// Clip a convex polygon to the part that OpenGL would draw.
+/// The sides of the screen are handled by rasterizing only the pixels inside it.
+fn clip(mut polygon: Vec<Corner>) -> Vec<Corner> {
+    let planes: [fn(&[f64; 4]) -> f64; 3] = [|c| c[3] + c[2], |c| c[3] - c[2], |c| c[3] - MIN_W];
+    for plane in planes.iter() {
+        let mut out = Vec::with_capacity(polygon.len() + 1);
+        for i in 0..polygon.len() {
+            let a = &polygon[i];
+            let b = &polygon[(i + 1) % polygon.len()];
+            let (da, db) = (plane(&a.clip), plane(&b.clip));
+            if da >= 0. {
+                out.push(*a);
+            }
+            if (da >= 0.) != (db >= 0.) {
+                out.push(a.lerp(b, da / (da - db)));
+            }
+        }
+        polygon = out;
+    }
+    polygon
+}
+
+/// Twice the signed area of the triangle `abc`; positive if it is counterclockwise.
+fn edge(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+/// Whether a pixel center exactly on the edge from `a` to `b` of a counterclockwise triangle
+/// belongs to it. Each such pixel belongs to exactly one of the triangles sharing the edge.
+fn is_top_left(a: [f64; 2], b: [f64; 2]) -> bool {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    (dy == 0. && dx < 0.) || dy < 0.
+}
@@ -0,0 +1,178 @@
+//! Finding the first block along a ray, for picking out the block the player is looking at.
+
+use crate::world::{Block, World};
+use nalgebra as na;
+
+/// Where a ray first meets a block.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub cell: [isize; 4],
+    /// The outward normal of the face the ray entered through, e.g. `[0, 1, 0, 0]` for the top face.
+    /// This is zero if the ray started inside the block.
+    pub normal: [isize; 4],
+    /// How far along the ray the block was entered.
+    pub distance: f64,
+}
+
+impl Hit {
+    /// The cell next to the hit one, on the side the ray came from.
+    pub fn adjacent(&self) -> [isize; 4] {
+        let mut out = self.cell;
+        for (x, n) in out.iter_mut().zip(&self.normal) {
+            *x += n;
+        }
+        out
+    }
+}
+
+impl World {
+    /// The first block other than air along the ray from `origin` in `direction`,
+    /// if there is one within `max_distance`.
+    /// There is none if the ray isn't made of finite numbers, or has no direction.
+    ///
+    /// This visits each cell the ray passes through in turn, by the method of Amanatides and Woo.
+    pub fn raycast(
+        &self,
+        origin: na::Vector4<f64>,
+        direction: na::Vector4<f64>,
+        max_distance: f64,
+    ) -> Option<Hit> {
+        // Otherwise, the loop below might never reach `max_distance`.
+        if !(origin.iter().chain(direction.iter()).all(|x| x.is_finite())
+            && max_distance.is_finite())
+        {
+            return None;
+        }
+        let direction = direction.try_normalize(0.)?;
+
+        let mut cell = [0; 4];
+        let mut step = [0; 4];
+        // How far along the ray the next face along each axis is.
+        let mut next = [f64::INFINITY; 4];
+        // How far along the ray it is between faces along each axis.
+        let mut spacing = [f64::INFINITY; 4];
+        for i in 0..4 {
+            cell[i] = origin[i].floor() as isize;
+            if direction[i] > 0. {
+                step[i] = 1;
+                next[i] = (cell[i] as f64 + 1. - origin[i]) / direction[i];
+                spacing[i] = 1. / direction[i];
+            } else if direction[i] < 0. {
+                step[i] = -1;
+                next[i] = (cell[i] as f64 - origin[i]) / direction[i];
+                spacing[i] = -1. / direction[i];
+            }
+        }
+
+        let mut normal = [0; 4];
+        let mut distance = 0.;
+        loop {
+            if *self.block(cell) != Block::Air {
+                return Some(Hit {
+                    cell,
+                    normal,
+                    distance,
+                });
+            }
+
+            let mut axis = 0;
+            for i in 1..4 {
+                if next[i] < next[axis] {
+                    axis = i;
+                }
+            }
+            if next[axis] > max_distance {
+                return None;
+            }
+
+            distance = next[axis];
+            cell[axis] += step[axis];
+            next[axis] += spacing[axis];
+            normal = [0; 4];
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A world with a single block, in the cell `[2, 2, 2, 2]`.
+    fn world() -> World {
+        let mut world = World::new([0; 4], [5; 4]);
+        *world.block_mut([2; 4]).unwrap() = Block::Stone;
+        world
+    }
+
+    fn center(cell: [isize; 4]) -> na::Vector4<f64> {
+        na::Vector4::from_iterator(cell.iter().map(|&x| x as f64 + 0.5))
+    }
+
+    #[test]
+    fn hits_each_face() {
+        let world = world();
+        for axis in 0..4 {
+            for &sign in &[1, -1] {
+                let mut start = [2; 4];
+                start[axis] -= 2 * sign;
+                let mut direction = na::Vector4::zeros();
+                direction[axis] = sign as f64;
+
+                let hit = world.raycast(center(start), direction, 10.).unwrap();
+                let mut normal = [0; 4];
+                normal[axis] = -sign;
+                assert_eq!(
+                    hit,
+                    Hit {
+                        cell: [2; 4],
+                        normal,
+                        distance: 1.5,
+                    }
+                );
+                assert_eq!(hit.adjacent(), {
+                    let mut cell = [2; 4];
+                    cell[axis] -= sign;
+                    cell
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn misses() {
+        let direction = na::Vector4::new(0., 1., 0., 0.);
+        assert_eq!(world().raycast(center([2, 3, 2, 2]), direction, 10.), None);
+        assert_eq!(world().raycast(center([1, 0, 2, 2]), direction, 10.), None);
+    }
+
+    #[test]
+    fn starts_inside_a_block() {
+        let hit = world()
+            .raycast(center([2; 4]), na::Vector4::new(1., 2., 3., 4.), 10.)
+            .unwrap();
+        assert_eq!(hit.normal, [0; 4]);
+        assert_eq!(hit.distance, 0.);
+    }
+
+    #[test]
+    fn stops_at_max_distance() {
+        let (origin, direction) = (center([0, 2, 2, 2]), na::Vector4::new(1., 0., 0., 0.));
+        assert_eq!(world().raycast(origin, direction, 1.4), None);
+        assert!(world().raycast(origin, direction, 1.6).is_some());
+    }
+
+    #[test]
+    fn ignores_rays_that_are_not_finite() {
+        let world = world();
+        let (origin, direction) = (center([0, 2, 2, 2]), na::Vector4::new(1., 0., 0., 0.));
+        let nan = na::Vector4::repeat(f64::NAN);
+        let infinite = na::Vector4::new(f64::INFINITY, 0., 0., 0.);
+        assert_eq!(world.raycast(nan, direction, 10.), None);
+        assert_eq!(world.raycast(origin, nan, 10.), None);
+        assert_eq!(world.raycast(infinite, direction, 10.), None);
+        assert_eq!(world.raycast(origin, infinite, 10.), None);
+        assert_eq!(world.raycast(origin, na::Vector4::zeros(), 10.), None);
+        assert_eq!(world.raycast(origin, direction, f64::NAN), None);
+    }
+}
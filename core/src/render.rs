@@ -0,0 +1,93 @@
+//! The data that every renderer draws from: per-vertex attributes and textures.
+//!
+//! Every frontend draws in two passes.
+//! First, each triangle adds `(1 - texel * color) * opacity * 0.5 * sign` to an RGBA float buffer,
+//! where `texel` is looked up at `texcoord.xy / texcoord.z` in layer `layer` of the textures.
+//! Then each pixel of that buffer `x` is displayed as `exp(-x)`.
+
+use crate::world;
+
+/// The side length of each texture, in texels.
+pub const TEXTURE_SIZE: usize = 256;
+
+/// The number of layers returned by `textures`.
+pub const TEXTURE_COUNT: usize = 2;
+
+/// The textures that materials can refer to, as `TEXTURE_COUNT` RGBA images,
+/// each `TEXTURE_SIZE` by `TEXTURE_SIZE`.
+pub fn textures() -> Vec<u8> {
+    let mut out = include_bytes!("../../resources/tex").to_vec();
+    // A plain white texture, for materials that should be a flat colour.
+    out.resize(out.len() + TEXTURE_SIZE * TEXTURE_SIZE * 4, 255);
+    out
+}
+
+/// The number of floats in `Vertex::to_array`.
+pub const FLOATS_PER_VERTEX: usize = 13;
+
+/// A corner of a triangle, with everything the shaders need to know about it.
+#[derive(Copy, Clone, Debug)]
+pub struct Vertex {
+    /// Homogeneous coordinates in the 3D screen.
+    pub position: [f32; 4],
+    /// Homogeneous texture coordinates.
+    pub texcoord: [f32; 3],
+    /// `-1` for the negated triangles produced by occlusion, `1` otherwise.
+    pub sign: f32,
+    pub color: [f32; 3],
+    pub opacity: f32,
+    /// Which of the `textures` to use.
+    pub layer: f32,
+}
+
+impl Vertex {
+    /// The attributes, in the order they're declared.
+    pub fn to_array(&self) -> [f32; FLOATS_PER_VERTEX] {
+        let [x, y, z, w] = self.position;
+        let [s, t, q] = self.texcoord;
+        let [r, g, b] = self.color;
+        [
+            x,
+            y,
+            z,
+            w,
+            s,
+            t,
+            q,
+            self.sign,
+            r,
+            g,
+            b,
+            self.opacity,
+            self.layer,
+        ]
+    }
+}
+
+/// The vertices of the triangles, three per triangle, with their materials decoded.
+pub fn vertices(triangles: &[render_4d::Triangle]) -> impl Iterator<Item = Vertex> + '_ {
+    triangles
+        .iter()
+        .flat_map(|render_4d::Triangle { vertices, negated }| {
+            let sign = if *negated { -1.0 } else { 1.0 };
+            vertices
+                .iter()
+                .map(move |render_4d::Vertex { position, texcoord }| {
+                    let material =
+                        world::Block::from_texture_coordinate(texcoord[1] / texcoord[2]).material();
+                    Vertex {
+                        position: [
+                            position[0] as f32,
+                            position[1] as f32,
+                            position[2] as f32,
+                            position[3] as f32,
+                        ],
+                        texcoord: [texcoord[0] as f32, texcoord[1] as f32, texcoord[2] as f32],
+                        sign,
+                        color: material.color,
+                        opacity: material.opacity,
+                        layer: material.texture as f32,
+                    }
+                })
+        })
+}
@@ -0,0 +1,191 @@
+//! Rotations of four-dimensional space, for pointing the camera in any direction.
+//!
+//! Axes are numbered as everywhere else: 0 is x, 1 is y (up), 2 is z, and 3 is w, which the camera looks along.
+
+use nalgebra as na;
+
+pub type Rotation4 = na::Rotation<f64, na::U4>;
+
+/// Keeps nearly parallel vectors from being treated as independent when building a frame.
+const EPSILON: f64 = 1e-6;
+
+/// The rotation by `angle` in the plane of axes `a` and `b`, which turns `a` towards `b`.
+pub fn plane(a: usize, b: usize, angle: f64) -> Rotation4 {
+    assert!(a != b && a < 4 && b < 4, "invalid plane of rotation");
+    let mut out = na::Matrix4::identity();
+    out[(a, a)] = angle.cos();
+    out[(b, a)] = angle.sin();
+    out[(a, b)] = -angle.sin();
+    out[(b, b)] = angle.cos();
+    Rotation4::from_matrix_unchecked(out)
+}
+
+/// A rotation that keeps y fixed, given as a unit quaternion acting on x, z and w in that order,
+/// as in `Spawn::horizontal_orientation`.
+pub fn horizontal(orientation: &na::UnitQuaternion<f64>) -> Rotation4 {
+    let mut out = orientation
+        .to_rotation_matrix()
+        .matrix()
+        .insert_row(1, 0.)
+        .insert_column(1, 0.);
+    out[(1, 1)] = 1.;
+    Rotation4::from_matrix_unchecked(out)
+}
+
+/// Tilt the view up by `angle`, turning w towards y.
+pub fn vertical(angle: f64) -> Rotation4 {
+    plane(3, 1, angle)
+}
+
+/// Correct the rounding errors that build up as rotations are composed.
+///
+/// The direction the camera looks in is kept as it is, then its up direction, and so on.
+pub fn renormalize(rotation: &Rotation4) -> Rotation4 {
+    let [x, y, z, w] = columns(rotation);
+    let [w, y, x, z] = orthonormalize([w, y, x, z]);
+    from_columns([x, y, z, w])
+}
+
+/// Split a rotation into a horizontal one, which keeps y fixed, and how far up the view is tilted,
+/// so that `horizontal * vertical(angle)` looks in the same direction as `rotation`, but without any roll.
+pub fn level(rotation: &Rotation4) -> (Rotation4, f64) {
+    let [x, y, z, w] = columns(rotation);
+    let angle = w[1].clamp(-1., 1.).asin();
+
+    let horizontal_part = |mut v: na::Vector4<f64>| {
+        v[1] = 0.;
+        v
+    };
+    // Looking straight up or down, the direction in front is the one behind or above the screen.
+    let forward = if horizontal_part(w).norm() > EPSILON {
+        horizontal_part(w)
+    } else {
+        horizontal_part(-y * w[1].signum())
+    };
+
+    let [w, y, x, z] = orthonormalize([forward, na::Vector4::y(), horizontal_part(x), z]);
+    let mut out = from_columns([x, y, z, w]);
+    // The last axis was only chosen up to sign; don't reflect the world.
+    if out.matrix().determinant() < 0. {
+        out = from_columns([x, y, -z, w]);
+    }
+    (out, angle)
+}
+
+fn columns(rotation: &Rotation4) -> [na::Vector4<f64>; 4] {
+    let m = rotation.matrix();
+    [
+        m.column(0).into_owned(),
+        m.column(1).into_owned(),
+        m.column(2).into_owned(),
+        m.column(3).into_owned(),
+    ]
+}
+
+fn from_columns(columns: [na::Vector4<f64>; 4]) -> Rotation4 {
+    Rotation4::from_matrix_unchecked(na::Matrix4::from_columns(&columns))
+}
+
+/// Gram-Schmidt: make each vector perpendicular to the ones before it, and of unit length.
+/// A vector that depends on the ones before it is replaced by one of the axes.
+fn orthonormalize(vectors: [na::Vector4<f64>; 4]) -> [na::Vector4<f64>; 4] {
+    let mut out: Vec<na::Vector4<f64>> = Vec::with_capacity(4);
+    for v in vectors.iter() {
+        let axes = (0..4).map(|i| na::Vector4::from_fn(|r, _| if r == i { 1. } else { 0. }));
+        let candidates = std::iter::once(*v).chain(axes);
+        for mut candidate in candidates {
+            for u in &out {
+                candidate -= u * u.dot(&candidate);
+            }
+            if candidate.norm() > EPSILON {
+                out.push(candidate.normalize());
+                break;
+            }
+        }
+    }
+    [out[0], out[1], out[2], out[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f64::consts::FRAC_PI_2;
+
+    fn assert_proper(rotation: &Rotation4) {
+        let m = rotation.matrix();
+        assert!(
+            (m.transpose() * m - na::Matrix4::identity()).norm() < 1e-9,
+            "{}",
+            m
+        );
+        assert!((m.determinant() - 1.).abs() < 1e-9, "{}", m);
+    }
+
+    fn assert_near(a: &Rotation4, b: &Rotation4) {
+        assert!(
+            (a.matrix() - b.matrix()).norm() < 1e-9,
+            "{} is not {}",
+            a,
+            b
+        );
+    }
+
+    /// Some rotations that turn every axis, including ones that look almost straight up or down.
+    fn examples() -> Vec<Rotation4> {
+        let mut out = Vec::new();
+        for &(a, b, c) in &[
+            (0.3, 0.2, 0.1),
+            (-1.2, 0.9, 2.5),
+            (2., -0.4, -1.),
+            (0.5, 1.5, 0.7),
+        ] {
+            out.push(plane(0, 3, a) * plane(3, 1, b) * plane(2, 1, c) * plane(0, 2, a - c));
+        }
+        out.push(plane(3, 1, FRAC_PI_2));
+        out.push(plane(0, 3, 0.4) * plane(3, 1, -FRAC_PI_2));
+        out
+    }
+
+    #[test]
+    fn level_is_a_rotation_with_a_level_horizon() {
+        for rotation in examples() {
+            let (horizontal, angle) = level(&rotation);
+            assert_proper(&horizontal);
+            // The horizontal part keeps y fixed.
+            assert!((horizontal * na::Vector4::y() - na::Vector4::y()).norm() < 1e-9);
+            // Undoing the tilt faces the same way.
+            let forward = rotation * na::Vector4::w();
+            let levelled = horizontal * vertical(angle) * na::Vector4::w();
+            assert!(
+                (forward - levelled).norm() < 1e-9,
+                "{} is not {}",
+                forward,
+                levelled
+            );
+        }
+    }
+
+    #[test]
+    fn level_finds_the_tilt() {
+        let orientation = na::UnitQuaternion::from_euler_angles(0.3, -1.1, 0.6);
+        for &angle in &[0., 0.4, -1.2] {
+            let (horizontal_part, found) = level(&(horizontal(&orientation) * vertical(angle)));
+            assert!((found - angle).abs() < 1e-9);
+            assert_near(&horizontal_part, &horizontal(&orientation));
+        }
+    }
+
+    #[test]
+    fn renormalize_repairs_drift() {
+        for rotation in examples() {
+            let drift = na::Matrix4::from_fn(|r, c| 1e-4 * ((r * 4 + c) as f64).sin());
+            let drifted = Rotation4::from_matrix_unchecked(rotation.matrix() + drift);
+            let repaired = renormalize(&drifted);
+            assert_proper(&repaired);
+            assert!((repaired.matrix() - rotation.matrix()).norm() < 1e-3);
+            // The direction the camera looks in is kept exactly.
+            let forward = drifted.matrix().column(3).normalize();
+            assert!((repaired.matrix().column(3) - forward).norm() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+/// Steps a simulation at a fixed rate, however often frames occur,
+/// so that the simulation doesn't depend on the frame rate.
+pub struct FixedTimestep {
+    step: f64,
+    accumulator: f64,
+}
+
+/// The most time, in seconds, that a single frame can advance the simulation by.
+/// This stops the simulation from trying to catch up after the program has been paused.
+const MAX_FRAME_TIME: f64 = 0.25;
+
+impl FixedTimestep {
+    /// `step` is the length of a simulation step, in seconds.
+    pub fn new(step: f64) -> Self {
+        Self {
+            step,
+            accumulator: 0.,
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Tell the timestep that `dt` seconds have passed, returning the number of steps to simulate.
+    pub fn advance(&mut self, dt: f64) -> u32 {
+        self.accumulator += dt.clamp(0., MAX_FRAME_TIME);
+        let steps = (self.accumulator / self.step).floor();
+        self.accumulator -= steps * self.step;
+        steps as u32
+    }
+
+    /// How far the current time is between the last step and the next, from 0 to 1.
+    /// Rendering should interpolate between the last two steps by this much.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_at_a_fixed_rate() {
+        let mut timestep = FixedTimestep::new(0.25);
+        assert_eq!(timestep.advance(0.1), 0);
+        assert_eq!(timestep.advance(0.2), 1);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-12);
+        assert_eq!(timestep.advance(0.2), 1);
+        assert!((timestep.alpha() - 0.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn long_frames_are_cut_short() {
+        let mut timestep = FixedTimestep::new(0.01);
+        let steps = timestep.advance(60.);
+        assert!(steps <= (MAX_FRAME_TIME / 0.01).round() as u32);
+        assert_eq!(timestep.advance(-1.), 0);
+    }
+}
@@ -0,0 +1,340 @@
+//! Touch controls, for phones and tablets, which have no keyboard, mouse or pointer lock.
+//!
+//! - A finger that starts on the joystick in the bottom left corner moves right/left and forward/backward.
+//! - A finger that starts on the joystick in the bottom right corner moves ana/kata and up/down.
+//! - Anywhere else, dragging one finger looks around, as the mouse does.
+//!   With two fingers, twisting looks ana/kata, pinching turns the 3D screen,
+//!   and dragging both up or down tilts it.
+//!
+//! Positions are given as fractions of the display's width and height, from the top left corner,
+//! but measured internally in units of the display's shorter side, so that gestures don't stretch.
+
+use crate::controls::Action;
+use core::f64::consts::*;
+
+/// How far a joystick's knob can move from its center, in units of the display's shorter side.
+const JOYSTICK_RADIUS: f64 = 0.12;
+
+/// How far a joystick's center is from the edges of the display, in the same units.
+const JOYSTICK_MARGIN: f64 = 0.2;
+
+/// A finger starting this many radii from a joystick's center grabs it.
+const JOYSTICK_GRAB: f64 = 1.5;
+
+/// How far the camera turns when a finger is dragged across the display's shorter side, in radians.
+const LOOK_SPEED: f64 = PI;
+
+/// How far the 3D screen turns when the fingers pinch to half as far apart, in radians.
+const PINCH_SPEED: f64 = 1.;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stick {
+    /// Moves right/left and forward/backward.
+    Walk,
+    /// Moves ana/kata and up/down.
+    Climb,
+}
+
+/// A joystick, for drawing on the display.
+#[derive(Copy, Clone, Debug)]
+pub struct Joystick {
+    pub stick: Stick,
+    /// As a fraction of the display's width and height.
+    pub center: [f64; 2],
+    /// As a fraction of the display's shorter side.
+    pub radius: f64,
+    /// Where the knob is, as a fraction of the display's width and height.
+    pub knob: [f64; 2],
+    /// Whether a finger is holding it.
+    pub held: bool,
+}
+
+/// How the camera and 3D screen should turn, as a result of fingers moving.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Gesture {
+    /// Rotations in the x-w, y-w and z-w planes, as `Game` accumulates mouse movement.
+    pub look: [f64; 3],
+    /// Changes to the 3D screen's angles, theta and phi.
+    pub screen: [f64; 2],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Finger {
+    id: i64,
+    /// Which joystick the finger is holding, if any.
+    stick: Option<Stick>,
+    position: [f64; 2],
+}
+
+/// The fingers touching the display.
+pub struct Touches {
+    fingers: Vec<Finger>,
+    /// The display's width and height, in units of its shorter side.
+    size: [f64; 2],
+}
+
+impl Default for Touches {
+    fn default() -> Self {
+        Self {
+            fingers: Vec::new(),
+            size: [1., 1.],
+        }
+    }
+}
+
+impl Touches {
+    /// Tell the touch controls the shape of the display, as its width divided by its height.
+    /// A ratio that isn't finite and positive, as from a display that hasn't been laid out yet, is ignored.
+    pub fn resize(&mut self, aspect_ratio: f64) {
+        if !(aspect_ratio.is_finite() && aspect_ratio > 0.) {
+            return;
+        }
+        self.size = if aspect_ratio > 1. {
+            [aspect_ratio, 1.]
+        } else {
+            [1., 1. / aspect_ratio]
+        };
+    }
+
+    pub fn start(&mut self, id: i64, position: [f64; 2]) {
+        let position = self.in_units(position);
+        let stick = [Stick::Walk, Stick::Climb].iter().copied().find(|&stick| {
+            !self.fingers.iter().any(|f| f.stick == Some(stick))
+                && distance(position, self.center(stick)) < JOYSTICK_RADIUS * JOYSTICK_GRAB
+        });
+        self.end(id);
+        self.fingers.push(Finger {
+            id,
+            stick,
+            position,
+        });
+    }
+
+    /// Move a finger, returning how that turns the camera and 3D screen.
+    pub fn move_to(&mut self, id: i64, position: [f64; 2]) -> Gesture {
+        let position = self.in_units(position);
+        let mut out = Gesture::default();
+
+        let index = match self.fingers.iter().position(|f| f.id == id) {
+            Some(index) => index,
+            None => return out,
+        };
+        let old = self.fingers[index].position;
+        self.fingers[index].position = position;
+        if self.fingers[index].stick.is_some() {
+            return out;
+        }
+
+        // Only the first two fingers that aren't on a joystick make gestures.
+        let mut gesture = self.fingers.iter().filter(|f| f.stick.is_none());
+        let (first, second) = (gesture.next(), gesture.next());
+        let other = match (first, second) {
+            (Some(a), Some(b)) if a.id == id => b.position,
+            (Some(a), Some(b)) if b.id == id => a.position,
+            (Some(a), None) if a.id == id => {
+                out.look[0] = (position[0] - old[0]) * LOOK_SPEED;
+                out.look[1] = (old[1] - position[1]) * LOOK_SPEED;
+                return out;
+            }
+            _ => return out,
+        };
+
+        let before = [old[0] - other[0], old[1] - other[1]];
+        let after = [position[0] - other[0], position[1] - other[1]];
+        if length(before) > 0. && length(after) > 0. {
+            // The twist is measured clockwise, as the display's y axis points down.
+            let twist = after[1].atan2(after[0]) - before[1].atan2(before[0]);
+            out.look[2] = (twist + PI).rem_euclid(2. * PI) - PI;
+            out.screen[0] = (length(before) / length(after)).log2() * PINCH_SPEED;
+        }
+        // The midpoint moves by half as much as the finger.
+        out.screen[1] = (old[1] - position[1]) / 2. * LOOK_SPEED;
+        out
+    }
+
+    pub fn end(&mut self, id: i64) {
+        self.fingers.retain(|f| f.id != id);
+    }
+
+    /// How much the joysticks are doing `action`, from -1 to 1, which is negative if they are doing the opposite.
+    pub fn analog(&self, action: Action) -> f64 {
+        let (stick, axis, sign) = match action {
+            Action::MoveRight => (Stick::Walk, 0, 1.),
+            Action::MoveForward => (Stick::Walk, 1, -1.),
+            Action::MoveAna => (Stick::Climb, 0, 1.),
+            Action::MoveUp => (Stick::Climb, 1, -1.),
+            _ => return 0.,
+        };
+        self.knob(stick)[axis] * sign
+    }
+
+    /// The joysticks, for drawing on the display.
+    pub fn joysticks(&self) -> [Joystick; 2] {
+        let joystick = |stick| {
+            let center = self.center(stick);
+            let knob = self.knob(stick);
+            Joystick {
+                stick,
+                center: self.as_fraction(center),
+                radius: JOYSTICK_RADIUS,
+                knob: self.as_fraction([
+                    center[0] + knob[0] * JOYSTICK_RADIUS,
+                    center[1] + knob[1] * JOYSTICK_RADIUS,
+                ]),
+                held: self.fingers.iter().any(|f| f.stick == Some(stick)),
+            }
+        };
+        [joystick(Stick::Walk), joystick(Stick::Climb)]
+    }
+
+    fn center(&self, stick: Stick) -> [f64; 2] {
+        let [width, height] = self.size;
+        match stick {
+            Stick::Walk => [JOYSTICK_MARGIN, height - JOYSTICK_MARGIN],
+            Stick::Climb => [width - JOYSTICK_MARGIN, height - JOYSTICK_MARGIN],
+        }
+    }
+
+    /// How far the knob of a joystick is pushed, with each coordinate from -1 to 1, and y down.
+    fn knob(&self, stick: Stick) -> [f64; 2] {
+        let finger = match self.fingers.iter().find(|f| f.stick == Some(stick)) {
+            Some(finger) => finger,
+            None => return [0., 0.],
+        };
+        let center = self.center(stick);
+        let offset = [
+            (finger.position[0] - center[0]) / JOYSTICK_RADIUS,
+            (finger.position[1] - center[1]) / JOYSTICK_RADIUS,
+        ];
+        let scale = length(offset).max(1.);
+        [offset[0] / scale, offset[1] / scale]
+    }
+
+    fn in_units(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        [x * self.size[0], y * self.size[1]]
+    }
+
+    fn as_fraction(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        [x / self.size[0], y / self.size[1]]
+    }
+}
+
+fn length([x, y]: [f64; 2]) -> f64 {
+    x.hypot(y)
+}
+
+fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    length([a[0] - b[0], a[1] - b[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} is not {}", a, b);
+    }
+
+    /// A display twice as wide as it is high, so the walking joystick is at `[0.1, 0.8]`.
+    fn wide() -> Touches {
+        let mut touches = Touches::default();
+        touches.resize(2.);
+        touches
+    }
+
+    #[test]
+    fn grabbing_a_joystick() {
+        let mut touches = wide();
+        touches.start(0, [0.5, 0.5]);
+        assert!(!touches.joysticks()[0].held);
+        touches.start(1, [0.11, 0.79]);
+        assert!(touches.joysticks()[0].held);
+        assert!(!touches.joysticks()[1].held);
+
+        // Moving a finger on a joystick doesn't look around.
+        let gesture = touches.move_to(1, [0.1, 0.75]);
+        assert_eq!(gesture.look, [0.; 3]);
+        assert_near(touches.analog(Action::MoveForward), 0.05 / JOYSTICK_RADIUS);
+
+        touches.end(1);
+        assert!(!touches.joysticks()[0].held);
+        assert_eq!(touches.analog(Action::MoveForward), 0.);
+    }
+
+    #[test]
+    fn knob_stays_within_the_joystick() {
+        let mut touches = wide();
+        touches.start(1, [0.1, 0.8]);
+        touches.move_to(1, [0.1, 0.3]);
+        assert_near(touches.analog(Action::MoveForward), 1.);
+        assert_near(touches.analog(Action::MoveRight), 0.);
+        let joystick = touches.joysticks()[0];
+        assert_near(joystick.knob[0], 0.1);
+        assert_near(joystick.knob[1], 0.8 - JOYSTICK_RADIUS);
+    }
+
+    #[test]
+    fn one_finger_looks() {
+        let mut touches = Touches::default();
+        touches.start(1, [0.5, 0.5]);
+        let gesture = touches.move_to(1, [0.6, 0.4]);
+        assert_near(gesture.look[0], 0.1 * LOOK_SPEED);
+        assert_near(gesture.look[1], 0.1 * LOOK_SPEED);
+        assert_near(gesture.look[2], 0.);
+        assert_eq!(gesture.screen, [0.; 2]);
+    }
+
+    #[test]
+    fn two_fingers_twist() {
+        let mut touches = Touches::default();
+        touches.start(1, [0.5, 0.5]);
+        touches.start(2, [0.6, 0.5]);
+        let gesture = touches.move_to(2, [0.5, 0.6]);
+        assert_near(gesture.look[2].abs(), FRAC_PI_2);
+        assert_near(gesture.screen[0], 0.);
+    }
+
+    #[test]
+    fn two_fingers_pinch() {
+        let mut touches = Touches::default();
+        touches.start(1, [0.5, 0.5]);
+        touches.start(2, [0.7, 0.5]);
+        let gesture = touches.move_to(2, [0.6, 0.5]);
+        assert_near(gesture.screen[0], PINCH_SPEED);
+        assert_near(gesture.look[2], 0.);
+        assert_eq!([gesture.look[0], gesture.look[1]], [0.; 2]);
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        // Moving across the width of a wide display turns further than moving across its height.
+        let mut touches = wide();
+        touches.start(1, [0.5, 0.5]);
+        let gesture = touches.move_to(1, [0.6, 0.6]);
+        assert_near(gesture.look[0], 0.2 * LOOK_SPEED);
+        assert_near(gesture.look[1], -0.1 * LOOK_SPEED);
+
+        let mut touches = Touches::default();
+        touches.resize(0.5);
+        let [walk, climb] = touches.joysticks();
+        assert_near(walk.center[0], JOYSTICK_MARGIN);
+        assert_near(walk.center[1], 1. - JOYSTICK_MARGIN / 2.);
+        assert_near(climb.center[0], 1. - JOYSTICK_MARGIN);
+        assert_eq!(walk.radius, JOYSTICK_RADIUS);
+    }
+
+    #[test]
+    fn ignores_bad_aspect_ratios() {
+        let mut touches = wide();
+        for &ratio in &[0., -1., f64::NAN, f64::INFINITY] {
+            touches.resize(ratio);
+        }
+        touches.start(1, [0.5, 0.5]);
+        let gesture = touches.move_to(1, [0.6, 0.6]);
+        assert_near(gesture.look[0], 0.2 * LOOK_SPEED);
+        assert_near(gesture.look[1], -0.1 * LOOK_SPEED);
+        let [walk, _] = touches.joysticks();
+        assert_near(walk.center[0], 0.1);
+        assert_near(walk.knob[1], 0.8);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::mesh::MeshCache;
+use nalgebra as na;
+pub use render_4d::Mesh;
+use std::collections::HashMap;
+
+/// The side length of a chunk, in blocks.
+pub(crate) const CHUNK_SIZE: isize = 8;
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// A four-dimensional grid of blocks.
+///
+/// The world occupies the cells `min[i] <= pos[i] < max[i]`; everything outside is air.
+/// Blocks are stored sparsely, in chunks of `CHUNK_SIZE`^4 cells, which are only allocated once written to.
+#[derive(Clone)]
+pub struct World {
+    min: [isize; 4],
+    max: [isize; 4],
+    chunks: HashMap<[isize; 4], Box<[Block; CHUNK_VOLUME]>>,
+    /// The faces of each chunk and the facets built from them, kept up to date by `block_mut`; see `World::mesh`.
+    pub(crate) mesh_cache: MeshCache,
+    pub spawn: Spawn,
+}
+
+/// Where the player starts, and which way they face.
+#[derive(Copy, Clone, Debug)]
+pub struct Spawn {
+    pub position: na::Vector4<f64>,
+    pub horizontal_orientation: na::UnitQuaternion<f64>,
+    pub vertical_angle: f64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Block {
+    Air,
+    Stone,
+    Glass,
+    Goal,
+    Hazard,
+}
+
+/// How a block looks and behaves.
+pub struct Material {
+    pub name: &'static str,
+    /// Multiplies the colour of the texture.
+    pub color: [f32; 3],
+    /// Which texture to draw the block's faces with.
+    pub texture: u32,
+    /// How strongly the block's faces are drawn, from 0 to 1.
+    /// Faces of blocks with an opacity less than 1 don't hide anything behind them.
+    pub opacity: f32,
+    /// Whether the player collides with the block.
+    pub solid: bool,
+    /// Whether the player eats the block by walking into it.
+    pub edible: bool,
+}
+
+/// The material of each block, indexed by `Block::id`.
+const MATERIALS: [Material; 5] = [
+    Material {
+        name: "air",
+        color: [1., 1., 1.],
+        texture: 0,
+        opacity: 0.,
+        solid: false,
+        edible: false,
+    },
+    Material {
+        name: "stone",
+        color: [0.6, 0.6, 0.6],
+        texture: 0,
+        opacity: 1.,
+        solid: true,
+        edible: false,
+    },
+    Material {
+        name: "glass",
+        color: [0.7, 0.9, 1.],
+        texture: 1,
+        opacity: 0.3,
+        solid: true,
+        edible: false,
+    },
+    Material {
+        name: "goal",
+        color: [1., 0.85, 0.2],
+        texture: 0,
+        opacity: 1.,
+        solid: false,
+        edible: true,
+    },
+    Material {
+        name: "hazard",
+        color: [1., 0.2, 0.1],
+        texture: 0,
+        opacity: 1.,
+        solid: false,
+        edible: false,
+    },
+];
+
+/// `World::mesh` has no way to tag a texture with its material,
+/// so the material is encoded in the second texture coordinate instead:
+/// a face of block `b` has that coordinate offset by `b.id() * MATERIAL_STRIDE`.
+///
+/// This limits the world to coordinates of absolute value at most `MAX_COORDINATE`.
+pub const MATERIAL_STRIDE: f64 = 1024.;
+
+/// How far from the origin a world may extend in each direction. See `MATERIAL_STRIDE`.
+pub const MAX_COORDINATE: isize = MATERIAL_STRIDE as isize / 2;
+
+impl World {
+    /// An empty world, occupying the cells `min[i] <= pos[i] < max[i]`.
+    /// The player spawns in the middle.
+    ///
+    /// Panics unless the extents pass `World::check_extents`.
+    pub fn new(min: [isize; 4], max: [isize; 4]) -> Self {
+        if let Err(err) = Self::check_extents(min, max) {
+            panic!("{}", err);
+        }
+        let center = |i: usize| (min[i] + max[i]) as f64 / 2.;
+        Self {
+            min,
+            max,
+            chunks: HashMap::new(),
+            mesh_cache: MeshCache::default(),
+            spawn: Spawn {
+                position: na::Vector4::new(center(0), center(1), center(2), center(3)),
+                horizontal_orientation: na::UnitQuaternion::identity(),
+                vertical_angle: 0.,
+            },
+        }
+    }
+
+    /// Check that the extents aren't negative, and lie within `MAX_COORDINATE` of the origin.
+    pub fn check_extents(min: [isize; 4], max: [isize; 4]) -> Result<(), String> {
+        for i in 0..4 {
+            if min[i] > max[i] {
+                return Err(format!("World has min {} > max {}", min[i], max[i]));
+            }
+            if min[i] < -MAX_COORDINATE || max[i] > MAX_COORDINATE {
+                return Err(format!(
+                    "World extends from {} to {}, but must stay within {} of the origin",
+                    min[i], max[i], MAX_COORDINATE
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn min(&self) -> [isize; 4] {
+        self.min
+    }
+
+    pub fn max(&self) -> [isize; 4] {
+        self.max
+    }
+
+    fn contains(&self, pos: [isize; 4]) -> bool {
+        (0..4).all(|i| self.min[i] <= pos[i] && pos[i] < self.max[i])
+    }
+
+    pub fn block(&self, pos: [isize; 4]) -> &Block {
+        if !self.contains(pos) {
+            return &Block::Air;
+        }
+        let (chunk, index) = chunk_index(pos);
+        match self.chunks.get(&chunk) {
+            Some(blocks) => &blocks[index],
+            None => &Block::Air,
+        }
+    }
+
+    pub fn block_mut(&mut self, pos: [isize; 4]) -> Option<&mut Block> {
+        if !self.contains(pos) {
+            return None;
+        }
+        self.mesh_cache.invalidate(pos);
+        let (chunk, index) = chunk_index(pos);
+        let blocks = self
+            .chunks
+            .entry(chunk)
+            .or_insert_with(|| Box::new([Block::Air; CHUNK_VOLUME]));
+        Some(&mut blocks[index])
+    }
+
+    /// How many edible blocks there are, i.e. how many the player has to eat to win.
+    pub fn edible_count(&self) -> usize {
+        self.chunks
+            .values()
+            .flat_map(|blocks| blocks.iter())
+            .filter(|block| block.material().edible)
+            .count()
+    }
+
+    /// Set every cell with `min[i] <= pos[i] < max[i]` to `block`.
+    /// Cells outside the world are ignored.
+    pub fn fill(&mut self, min: [isize; 4], max: [isize; 4], block: Block) {
+        let mut min = min;
+        let mut max = max;
+        for i in 0..4 {
+            min[i] = min[i].max(self.min[i]);
+            max[i] = max[i].min(self.max[i]);
+        }
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    for w in min[3]..max[3] {
+                        if let Some(b) = self.block_mut([x, y, z, w]) {
+                            *b = block;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Split a position into the coordinates of its chunk, and its index within that chunk.
+fn chunk_index(pos: [isize; 4]) -> ([isize; 4], usize) {
+    let mut chunk = [0; 4];
+    let mut index = 0;
+    for i in 0..4 {
+        chunk[i] = pos[i].div_euclid(CHUNK_SIZE);
+        index = index * CHUNK_SIZE as usize + pos[i].rem_euclid(CHUNK_SIZE) as usize;
+    }
+    (chunk, index)
+}
+
+/// The coordinates of the chunk containing a position.
+pub(crate) fn chunk_of(pos: [isize; 4]) -> [isize; 4] {
+    chunk_index(pos).0
+}
+
+impl Block {
+    pub const ALL: [Block; 5] = [
+        Block::Air,
+        Block::Stone,
+        Block::Glass,
+        Block::Goal,
+        Block::Hazard,
+    ];
+
+    pub fn id(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_id(id: usize) -> Option<Self> {
+        Self::ALL.get(id).copied()
+    }
+
+    pub fn material(self) -> &'static Material {
+        &MATERIALS[self.id()]
+    }
+
+    /// Look up a block by the name of its material.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|block| block.material().name == name)
+    }
+
+    pub(crate) fn texture_offset(self) -> f64 {
+        self.id() as f64 * MATERIAL_STRIDE
+    }
+
+    /// Recover the block from the second texture coordinate of one of its faces.
+    /// See `MATERIAL_STRIDE`.
+    pub fn from_texture_coordinate(t: f64) -> Self {
+        let id = (t / MATERIAL_STRIDE).round();
+        if id < 0. {
+            Block::Air
+        } else {
+            Self::from_id(id as usize).unwrap_or(Block::Air)
+        }
+    }
+
+    pub(crate) fn is_opaque(self) -> bool {
+        self.material().opacity >= 1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_coordinates_decode_at_the_limits() {
+        let limit = MAX_COORDINATE as f64;
+        for &block in Block::ALL.iter() {
+            // Texture coordinates lie strictly inside the cells, so never quite reach the limit.
+            for &t in &[-limit + 0.05, 0., limit - 0.05] {
+                assert_eq!(
+                    Block::from_texture_coordinate(t + block.texture_offset()),
+                    block
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn extents_must_stay_within_the_limit() {
+        let (min, max) = ([-MAX_COORDINATE; 4], [MAX_COORDINATE; 4]);
+        assert_eq!(World::check_extents(min, max), Ok(()));
+        assert!(World::check_extents([-MAX_COORDINATE - 1, 0, 0, 0], max).is_err());
+        assert!(World::check_extents(min, [0, 0, MAX_COORDINATE + 1, 0]).is_err());
+        assert!(World::check_extents([1, 0, 0, 0], [0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_worlds_beyond_the_limit() {
+        World::new([0; 4], [MAX_COORDINATE + 1, 1, 1, 1]);
+    }
+}
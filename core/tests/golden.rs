@@ -0,0 +1,207 @@
+//! Renders fixed scenes with the software rasterizer, and compares them against the
+//! reference images in `tests/golden`.
+//!
+//! A missing reference image is an error. Only with `UPDATE_GOLDEN=1` set does the test save what
+//! it rendered as the new reference, which should be looked at before it's committed.
+//! When an image doesn't match its reference, the rendered image and a diff are saved to
+//! `target/golden`; in the diff, differing pixels are red.
+
+use four_dimensions_core::generate::Generator;
+use four_dimensions_core::image::Image;
+use four_dimensions_core::raster::Rasterizer;
+use four_dimensions_core::world::{Block, World};
+use four_dimensions_core::{level, Game};
+use nalgebra as na;
+use std::path::PathBuf;
+
+const SIZE: usize = 256;
+/// How far apart two channels can be, out of 255, and still count as equal.
+const TOLERANCE: u8 = 2;
+/// The fraction of pixels that may differ, to allow for rounding along edges.
+const MAX_DIFFERING: f64 = 0.001;
+
+struct Camera {
+    position: [f64; 4],
+    /// An axis and angle, as in `UnitQuaternion::from_scaled_axis`.
+    horizontal_orientation: [f64; 3],
+    vertical_angle: f64,
+    screen_theta: f64,
+    screen_phi: f64,
+}
+
+fn render(mut world: World, camera: Camera) -> Image<u8> {
+    let [x, y, z, w] = camera.position;
+    let [i, j, k] = camera.horizontal_orientation;
+    world.spawn.position = na::Vector4::new(x, y, z, w);
+    world.spawn.horizontal_orientation =
+        na::UnitQuaternion::from_scaled_axis(na::Vector3::new(i, j, k));
+    world.spawn.vertical_angle = camera.vertical_angle;
+
+    let mut game = Game::new(world);
+    game.screen_theta = camera.screen_theta;
+    game.screen_phi = camera.screen_phi;
+
+    let scene = game.scene();
+    Rasterizer::new().render(scene.triangles, scene.screen, SIZE, SIZE)
+}
+
+fn check(name: &str, actual: &Image<u8>) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let reference_path = root.join("tests/golden").join(format!("{}.png", name));
+    let output_dir = root.join("../target/golden");
+
+    if std::env::var("UPDATE_GOLDEN").as_deref() == Ok("1") {
+        std::fs::write(&reference_path, actual.to_png().unwrap()).unwrap();
+        eprintln!(
+            "Saved {}. Check it before committing it.",
+            reference_path.display()
+        );
+        return;
+    }
+    assert!(
+        reference_path.exists(),
+        "There is no reference image at {}. Run the tests with UPDATE_GOLDEN=1 to render one, check it, and commit it.",
+        reference_path.display()
+    );
+
+    let expected = Image::from_png(&std::fs::read(&reference_path).unwrap()).unwrap();
+    assert_eq!(
+        (expected.width, expected.height),
+        (actual.width, actual.height),
+        "{} has the wrong size",
+        name
+    );
+
+    let mut diff = Image::new(actual.width, actual.height, [0, 0, 0, 255]);
+    let mut differing = 0;
+    for ((d, e), a) in diff
+        .pixels
+        .iter_mut()
+        .zip(&expected.pixels)
+        .zip(&actual.pixels)
+    {
+        let same = e.iter().zip(a).all(|(&e, &a)| e.abs_diff(a) <= TOLERANCE);
+        if same {
+            // A faded copy of the reference, for context.
+            let gray = (u16::from(e[0]) + u16::from(e[1]) + u16::from(e[2])) / 12 + 128;
+            *d = [gray as u8, gray as u8, gray as u8, 255];
+        } else {
+            differing += 1;
+            *d = [255, 0, 0, 255];
+        }
+    }
+
+    let fraction = f64::from(differing) / actual.pixels.len() as f64;
+    if fraction > MAX_DIFFERING {
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let actual_path = output_dir.join(format!("{}.actual.png", name));
+        let diff_path = output_dir.join(format!("{}.diff.png", name));
+        std::fs::write(&actual_path, actual.to_png().unwrap()).unwrap();
+        std::fs::write(&diff_path, diff.to_png().unwrap()).unwrap();
+        panic!(
+            "{} differs from its reference in {} pixels. See {} and {}.",
+            name,
+            differing,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+/// A stone floor, with one block of each other material standing on it.
+fn materials() -> World {
+    let mut world = World::new([0, 0, 0, 0], [5, 4, 5, 5]);
+    world.fill([0, 0, 0, 0], [5, 1, 5, 5], Block::Stone);
+    for &(pos, block) in &[
+        ([1, 1, 2, 2], Block::Glass),
+        ([2, 1, 3, 2], Block::Goal),
+        ([3, 1, 2, 3], Block::Hazard),
+        ([2, 1, 1, 2], Block::Stone),
+    ] {
+        *world.block_mut(pos).unwrap() = block;
+    }
+    world
+}
+
+#[test]
+fn default_level() {
+    let world = World::load(level::DEFAULT_LEVEL).unwrap();
+    let image = render(
+        world,
+        Camera {
+            position: [1.5, 1.5, 1.5, -2.5],
+            horizontal_orientation: [0., 0., 0.],
+            vertical_angle: 0.,
+            screen_theta: 0.3,
+            screen_phi: -0.2,
+        },
+    );
+    check("default_level", &image);
+}
+
+#[test]
+fn materials_from_above() {
+    let image = render(
+        materials(),
+        Camera {
+            position: [2.5, 3., 2.5, -0.5],
+            horizontal_orientation: [0., 0.3, 0.],
+            vertical_angle: -0.6,
+            screen_theta: 0.3,
+            screen_phi: -0.2,
+        },
+    );
+    check("materials_from_above", &image);
+}
+
+#[test]
+fn turned_ana() {
+    let image = render(
+        materials(),
+        Camera {
+            position: [2.5, 2., 2.5, 0.5],
+            horizontal_orientation: [0.7, 0., 0.],
+            vertical_angle: -0.3,
+            screen_theta: 0.3,
+            screen_phi: -0.2,
+        },
+    );
+    check("turned_ana", &image);
+}
+
+#[test]
+fn screen_rotated() {
+    let image = render(
+        materials(),
+        Camera {
+            position: [2.5, 3., 2.5, -0.5],
+            horizontal_orientation: [0., 0.3, 0.],
+            vertical_angle: -0.6,
+            screen_theta: 1.2,
+            screen_phi: 0.7,
+        },
+    );
+    check("screen_rotated", &image);
+}
+
+#[test]
+fn generated() {
+    let world = Generator::default().generate(7, [-4, 0, -4, -4], [4, 8, 4, 4]);
+    let spawn = world.spawn;
+    let image = render(
+        world,
+        Camera {
+            position: [
+                spawn.position[0],
+                spawn.position[1],
+                spawn.position[2],
+                spawn.position[3],
+            ],
+            horizontal_orientation: [0., 0., 0.],
+            vertical_angle: -0.4,
+            screen_theta: 0.3,
+            screen_phi: -0.2,
+        },
+    );
+    check("generated", &image);
+}
@@ -0,0 +1,424 @@
+//! The game as a desktop application, with the same controls as the web build.
+//!
+//! Run with `--help` for the command-line options.
+//! With `--screenshot` or `--accumulation`, it renders a picture on the CPU instead of opening a window.
+
+#![forbid(unsafe_code)]
+
+mod options;
+mod render;
+
+use four_dimensions_core::phase::{Event as PhaseEvent, Phase};
+use four_dimensions_core::{fps, projector, raster, Game, Input};
+use glium::glutin;
+use glutin::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+use glutin::event_loop::ControlFlow;
+
+/// How many pixels a browser scrolls per line, so the scroll wheel feels the same as on the web.
+const PIXELS_PER_LINE: f64 = 50.;
+
+fn main() {
+    let options = options::Options::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    let headless = options.screenshot.is_some() || options.accumulation.is_some();
+
+    // Restarting starts again from the world as it was loaded.
+    let world = options.world.clone();
+
+    // In a window, find the visible triangles on another thread, so that moving doesn't stutter.
+    let mut game = if headless {
+        Game::new(options.world)
+    } else {
+        let projector = projector::Threaded::new(options.world.clone());
+        Game::with_projector(options.world, Box::new(projector))
+    };
+    if let Some(theta) = options.screen_theta {
+        game.screen_theta = theta;
+    }
+    if let Some(phi) = options.screen_phi {
+        game.screen_phi = phi;
+    }
+    game.controls = options.controls;
+
+    if headless {
+        let result = screenshot(
+            &mut game,
+            options.size,
+            options.screenshot.as_ref(),
+            options.accumulation.as_ref(),
+        );
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let window = glutin::window::WindowBuilder::new()
+        .with_title("four-dimensions")
+        .with_inner_size(glutin::dpi::LogicalSize::new(800., 800.));
+    let context = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (3, 3)))
+        .with_gl_profile(glutin::GlProfile::Core)
+        .with_vsync(true);
+    let display = glium::Display::new(window, context, &event_loop).unwrap_or_else(|err| {
+        eprintln!("Failed to open a window: {}", err);
+        std::process::exit(1);
+    });
+    let mut renderer = render::Renderer::new(&display).unwrap_or_else(|err| {
+        eprintln!("Failed to set up rendering: {}", err);
+        std::process::exit(1);
+    });
+
+    let (width, height) = display.get_framebuffer_dimensions();
+    game.resize(f64::from(width), f64::from(height));
+
+    let mut fps: Option<fps::FrameCounter> = None;
+    let mut captured = false;
+    let start = std::time::Instant::now();
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) => {
+                    game.resize(f64::from(size.width), f64::from(size.height));
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } if !captured => {
+                    captured = capture(&display, true);
+                    if captured {
+                        game.handle(PhaseEvent::Start);
+                        game.handle(PhaseEvent::Resume);
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button,
+                    ..
+                } if captured => {
+                    if let Some(button) = button_number(button) {
+                        game.update(Input::MouseDown(button));
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Released,
+                    button,
+                    ..
+                } => {
+                    if let Some(button) = button_number(button) {
+                        game.update(Input::MouseUp(button));
+                    }
+                }
+                WindowEvent::Focused(false) => {
+                    captured = capture(&display, false);
+                    game.handle(PhaseEvent::Pause);
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state,
+                            scancode,
+                            virtual_keycode,
+                            ..
+                        },
+                    ..
+                } => {
+                    // Like the browser's pointer lock, escape releases the mouse.
+                    if virtual_keycode == Some(VirtualKeyCode::Escape) {
+                        captured = capture(&display, false);
+                        game.handle(PhaseEvent::Pause);
+                    }
+                    if virtual_keycode == Some(VirtualKeyCode::Return)
+                        && state == ElementState::Pressed
+                        && game.phase().can_restart()
+                    {
+                        let projector = projector::Threaded::new(world.clone());
+                        game.restart(world.clone(), Box::new(projector));
+                        captured = capture(&display, true);
+                    }
+                    if let Some(code) = key_code(scancode, virtual_keycode) {
+                        game.update(match state {
+                            ElementState::Pressed => Input::KeyDown(code),
+                            ElementState::Released => Input::KeyUp(code),
+                        });
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } if captured => {
+                    // Browsers report scrolling down as positive; winit reports it as negative.
+                    let pixels = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => f64::from(y) * PIXELS_PER_LINE,
+                        MouseScrollDelta::PixelDelta(p) => p.y,
+                    };
+                    game.update(Input::MouseWheel(-pixels));
+                }
+                _ => {}
+            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (x, y) },
+                ..
+            } if captured => {
+                game.update(Input::MouseMove([x, y]));
+            }
+            Event::MainEventsCleared => display.gl_window().window().request_redraw(),
+            Event::RedrawRequested(_) => {
+                let time = start.elapsed().as_secs_f64() * 1e3;
+                if let Some(fps) = &mut fps {
+                    let dt = fps.frame(time);
+
+                    let (eaten, left) = game.tesseracts();
+                    let seconds = game.time().round() as u64;
+                    let status = match game.phase() {
+                        Phase::Title => format!(
+                            "eat all {} tesseracts by walking into them | click to start",
+                            eaten + left
+                        ),
+                        Phase::Playing => format!(
+                            "tesseracts: {} eaten, {} left | movement: {:?} (press F to change) | free look: {} | block: {}",
+                            eaten,
+                            left,
+                            game.movement,
+                            if game.free_look { "on" } else { "off" },
+                            game.selected.material().name
+                        ),
+                        Phase::Paused => "paused | click to carry on, or press Enter to restart".to_string(),
+                        Phase::Won => format!(
+                            "you ate all {} tesseracts in {}:{:02}! | press Enter to play again",
+                            eaten,
+                            seconds / 60,
+                            seconds % 60
+                        ),
+                        Phase::Restarting => "restarting...".to_string(),
+                    };
+                    display
+                        .gl_window()
+                        .window()
+                        .set_title(&format!("four-dimensions | {} | {}", fps, status));
+
+                    game.advance(dt);
+                    // Give the mouse back once the game is over.
+                    if game.phase() == Phase::Won && captured {
+                        captured = capture(&display, false);
+                    }
+                } else {
+                    fps = Some(fps::FrameCounter::new(time));
+                }
+
+                let scene = game.scene();
+                if let Err(err) = renderer.draw(&display, &scene) {
+                    eprintln!("{}", err);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+/// Render the game's current view on the CPU, and save it.
+fn screenshot(
+    game: &mut Game,
+    size: usize,
+    path: Option<&String>,
+    accumulation_path: Option<&String>,
+) -> Result<(), String> {
+    let scene = game.scene();
+    let accumulation =
+        raster::Rasterizer::new().accumulate(scene.triangles, scene.screen, size, size);
+
+    if let Some(path) = path {
+        let image = accumulation.tone_map();
+        let bytes = if path.to_lowercase().ends_with(".ppm") {
+            image.to_ppm()
+        } else {
+            image.to_png()?
+        };
+        std::fs::write(path, bytes).map_err(|err| format!("Failed to write {}: {}", path, err))?;
+    }
+    if let Some(path) = accumulation_path {
+        std::fs::write(path, accumulation.to_pfm())
+            .map_err(|err| format!("Failed to write {}: {}", path, err))?;
+    }
+    Ok(())
+}
+
+/// Capture or release the mouse, returning whether it is now captured.
+fn capture(display: &glium::Display, capture: bool) -> bool {
+    use glutin::window::CursorGrabMode;
+
+    let gl_window = display.gl_window();
+    let window = gl_window.window();
+    if capture {
+        // Not every platform can lock the cursor in place, but confining it works nearly as well.
+        let grabbed = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+            .is_ok();
+        window.set_cursor_visible(!grabbed);
+        grabbed
+    } else {
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+        window.set_cursor_visible(true);
+        false
+    }
+}
+
+/// The number the browser gives a mouse button.
+fn button_number(button: MouseButton) -> Option<i16> {
+    match button {
+        MouseButton::Left => Some(0),
+        MouseButton::Middle => Some(1),
+        MouseButton::Right => Some(2),
+        MouseButton::Other(_) => None,
+    }
+}
+
+/// The name the browser gives a key, by where it is on the keyboard, as by `KeyboardEvent.code`.
+///
+/// The keys that type characters move around between layouts, so they are found by their scancode;
+/// the others are the same on every layout.
+fn key_code(scancode: u32, key: Option<VirtualKeyCode>) -> Option<String> {
+    use VirtualKeyCode::*;
+
+    if let Some(&(_, code)) = CHARACTER_KEYS.iter().find(|&&(s, _)| s == scancode) {
+        return Some(code.to_string());
+    }
+    Some(
+        match key? {
+            Space => "Space",
+            LShift => "ShiftLeft",
+            RShift => "ShiftRight",
+            LControl => "ControlLeft",
+            RControl => "ControlRight",
+            LAlt => "AltLeft",
+            RAlt => "AltRight",
+            Tab => "Tab",
+            Return => "Enter",
+            Back => "Backspace",
+            Escape => "Escape",
+            Left => "ArrowLeft",
+            Right => "ArrowRight",
+            Up => "ArrowUp",
+            Down => "ArrowDown",
+            _ => return None,
+        }
+        .to_string(),
+    )
+}
+
+/// The scancodes of the keys that type characters, on a US keyboard.
+/// Windows and Linux both number them as the original PC keyboard did.
+#[cfg(not(target_os = "macos"))]
+const CHARACTER_KEYS: [(u32, &str); 47] = [
+    (2, "Digit1"),
+    (3, "Digit2"),
+    (4, "Digit3"),
+    (5, "Digit4"),
+    (6, "Digit5"),
+    (7, "Digit6"),
+    (8, "Digit7"),
+    (9, "Digit8"),
+    (10, "Digit9"),
+    (11, "Digit0"),
+    (12, "Minus"),
+    (13, "Equal"),
+    (16, "KeyQ"),
+    (17, "KeyW"),
+    (18, "KeyE"),
+    (19, "KeyR"),
+    (20, "KeyT"),
+    (21, "KeyY"),
+    (22, "KeyU"),
+    (23, "KeyI"),
+    (24, "KeyO"),
+    (25, "KeyP"),
+    (26, "BracketLeft"),
+    (27, "BracketRight"),
+    (30, "KeyA"),
+    (31, "KeyS"),
+    (32, "KeyD"),
+    (33, "KeyF"),
+    (34, "KeyG"),
+    (35, "KeyH"),
+    (36, "KeyJ"),
+    (37, "KeyK"),
+    (38, "KeyL"),
+    (39, "Semicolon"),
+    (40, "Quote"),
+    (41, "Backquote"),
+    (43, "Backslash"),
+    (44, "KeyZ"),
+    (45, "KeyX"),
+    (46, "KeyC"),
+    (47, "KeyV"),
+    (48, "KeyB"),
+    (49, "KeyN"),
+    (50, "KeyM"),
+    (51, "Comma"),
+    (52, "Period"),
+    (53, "Slash"),
+];
+
+/// The virtual key codes of the keys that type characters, on a US keyboard, as macOS numbers them.
+#[cfg(target_os = "macos")]
+const CHARACTER_KEYS: [(u32, &str); 47] = [
+    (0, "KeyA"),
+    (1, "KeyS"),
+    (2, "KeyD"),
+    (3, "KeyF"),
+    (4, "KeyH"),
+    (5, "KeyG"),
+    (6, "KeyZ"),
+    (7, "KeyX"),
+    (8, "KeyC"),
+    (9, "KeyV"),
+    (11, "KeyB"),
+    (12, "KeyQ"),
+    (13, "KeyW"),
+    (14, "KeyE"),
+    (15, "KeyR"),
+    (16, "KeyY"),
+    (17, "KeyT"),
+    (18, "Digit1"),
+    (19, "Digit2"),
+    (20, "Digit3"),
+    (21, "Digit4"),
+    (22, "Digit6"),
+    (23, "Digit5"),
+    (24, "Equal"),
+    (25, "Digit9"),
+    (26, "Digit7"),
+    (27, "Minus"),
+    (28, "Digit8"),
+    (29, "Digit0"),
+    (30, "BracketRight"),
+    (31, "KeyO"),
+    (32, "KeyU"),
+    (33, "BracketLeft"),
+    (34, "KeyI"),
+    (35, "KeyP"),
+    (37, "KeyL"),
+    (38, "KeyJ"),
+    (39, "Quote"),
+    (40, "KeyK"),
+    (41, "Semicolon"),
+    (42, "Backslash"),
+    (43, "Comma"),
+    (44, "Slash"),
+    (45, "KeyN"),
+    (46, "KeyM"),
+    (47, "Period"),
+    (50, "Backquote"),
+];
@@ -0,0 +1,146 @@
+//! Command-line options.
+
+use four_dimensions_core::{controls, generate, level, world};
+use nalgebra as na;
+
+pub const USAGE: &str = "\
+Usage: four-dimensions [LEVEL_FILE | --seed SEED] [OPTIONS]
+
+Camera options, which replace the level's spawn point:
+    --spawn X Y Z W          where the player starts
+    --orientation W I J K    their horizontal orientation, as a unit quaternion
+    --vertical-angle ANGLE   how far up they look, in radians
+    --screen-theta ANGLE     how far the 3D screen is turned, in radians
+    --screen-phi ANGLE       how far the 3D screen is tilted, in radians
+
+Control options:
+    --controls FILE          read the key bindings from FILE, or save the defaults there if it doesn't exist
+
+Screenshot options, which render without opening a window:
+    --screenshot FILE        save the picture, as PNG or PPM depending on the extension
+    --accumulation FILE      save the float buffer from the first rendering pass, as PFM
+    --size PIXELS            the side length of the pictures (default 800)";
+
+pub struct Options {
+    pub world: world::World,
+    pub screen_theta: Option<f64>,
+    pub screen_phi: Option<f64>,
+    pub controls: controls::Controls,
+    pub screenshot: Option<String>,
+    pub accumulation: Option<String>,
+    pub size: usize,
+}
+
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut path = None;
+        let mut seed = None;
+        let mut spawn = None;
+        let mut orientation = None;
+        let mut vertical_angle = None;
+        let mut screen_theta = None;
+        let mut screen_phi = None;
+        let mut controls_path: Option<String> = None;
+        let mut screenshot = None;
+        let mut accumulation = None;
+        let mut size = 800;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => seed = Some(parse(&arg, &mut args)?),
+                "--spawn" => spawn = Some(parse_4(&arg, &mut args)?),
+                "--orientation" => orientation = Some(parse_4(&arg, &mut args)?),
+                "--vertical-angle" => vertical_angle = Some(parse(&arg, &mut args)?),
+                "--screen-theta" => screen_theta = Some(parse(&arg, &mut args)?),
+                "--screen-phi" => screen_phi = Some(parse(&arg, &mut args)?),
+                "--controls" => controls_path = Some(parse(&arg, &mut args)?),
+                "--screenshot" => screenshot = Some(parse(&arg, &mut args)?),
+                "--accumulation" => accumulation = Some(parse(&arg, &mut args)?),
+                "--size" => size = parse(&arg, &mut args)?,
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                _ if arg.starts_with("--") => {
+                    return Err(format!("Unknown option `{}`\n\n{}", arg, USAGE))
+                }
+                _ if path.is_none() => path = Some(arg),
+                _ => return Err(format!("Unexpected argument `{}`\n\n{}", arg, USAGE)),
+            }
+        }
+
+        if size == 0 {
+            return Err("The size must be at least one pixel".to_string());
+        }
+
+        let mut world = match (path, seed) {
+            (None, None) => world::World::load(level::DEFAULT_LEVEL)?,
+            (Some(path), None) => {
+                let bytes = std::fs::read(&path)
+                    .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+                world::World::load(&bytes)?
+            }
+            (None, Some(seed)) => generate::Generator::default().generate(
+                seed,
+                generate::DEFAULT_MIN,
+                generate::DEFAULT_MAX,
+            ),
+            (Some(_), Some(_)) => return Err("Give either a level or a seed, not both".to_string()),
+        };
+
+        if let Some([x, y, z, w]) = spawn {
+            world.spawn.position = na::Vector4::new(x, y, z, w);
+        }
+        if let Some([w, i, j, k]) = orientation {
+            world.spawn.horizontal_orientation =
+                na::UnitQuaternion::from_quaternion(na::Quaternion::new(w, i, j, k));
+        }
+        if let Some(angle) = vertical_angle {
+            world.spawn.vertical_angle = angle;
+        }
+
+        let controls = match controls_path {
+            None => controls::Controls::default(),
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => controls::Controls::load(&text)
+                    .map_err(|err| format!("Failed to load {}: {}", path, err))?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    let controls = controls::Controls::default();
+                    std::fs::write(&path, controls.save())
+                        .map_err(|err| format!("Failed to write {}: {}", path, err))?;
+                    controls
+                }
+                Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+            },
+        };
+
+        Ok(Self {
+            world,
+            screen_theta,
+            screen_phi,
+            controls,
+            screenshot,
+            accumulation,
+            size,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(
+    key: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String> {
+    let arg = args
+        .next()
+        .ok_or_else(|| format!("Expected a value after `{}`", key))?;
+    arg.parse()
+        .map_err(|_| format!("Could not parse `{}` after `{}`", arg, key))
+}
+
+fn parse_4<T: std::str::FromStr + Default + Copy>(
+    key: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<[T; 4], String> {
+    let mut out = [T::default(); 4];
+    for x in out.iter_mut() {
+        *x = parse(key, args)?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,301 @@
+//! The same two-pass pipeline as the web build's `render` module, in desktop OpenGL.
+
+use four_dimensions_core::{render, Scene};
+use glium::{implement_vertex, uniform, Surface};
+
+const TO_TEX_VERTEX_SHADER: &str = r#"#version 330 core
+
+in vec4 pos;
+in vec3 texcoord;
+in float sign;
+in vec3 color;
+in float opacity;
+in float layer;
+
+out vec3 vtexcoord;
+out float vsign;
+out vec3 vcolor;
+out float vopacity;
+flat out float vlayer;
+
+uniform mat4 proj_3d_screen;
+
+void main() {
+    vsign = sign;
+    vtexcoord = texcoord;
+    vcolor = color;
+    vopacity = opacity;
+    vlayer = layer;
+    gl_Position = proj_3d_screen * pos;
+}
+
+"#;
+
+const TO_TEX_FRAGMENT_SHADER: &str = r#"#version 330 core
+
+in vec3 vtexcoord;
+in float vsign;
+in vec3 vcolor;
+in float vopacity;
+flat in float vlayer;
+
+out vec4 color;
+
+uniform sampler2DArray tex;
+
+void main() {
+    vec3 texel = texture(tex, vec3(vtexcoord.xy / vtexcoord.z, vlayer)).rgb;
+    color = vec4((1.0 - texel * vcolor) * vopacity * 0.5 * vsign, 1.0);
+}
+
+"#;
+
+const FROM_TEX_VERTEX_SHADER: &str = r#"#version 330 core
+
+in vec2 coord;
+out vec2 vcoord;
+
+void main() {
+	vcoord = coord;
+	gl_Position = vec4(coord * 2.0 - 1.0, 0.0, 1.0);
+}
+
+"#;
+
+const FROM_TEX_FRAGMENT_SHADER: &str = r#"#version 330 core
+
+in vec2 vcoord;
+out vec4 color;
+uniform sampler2D tex;
+
+void main() {
+	color = exp(-texture(tex, vcoord));
+}
+
+"#;
+
+#[derive(Copy, Clone)]
+struct Vertex {
+    pos: [f32; 4],
+    texcoord: [f32; 3],
+    sign: f32,
+    color: [f32; 3],
+    opacity: f32,
+    layer: f32,
+}
+implement_vertex!(Vertex, pos, texcoord, sign, color, opacity, layer);
+
+impl From<render::Vertex> for Vertex {
+    fn from(v: render::Vertex) -> Self {
+        Self {
+            pos: v.position,
+            texcoord: v.texcoord,
+            sign: v.sign,
+            color: v.color,
+            opacity: v.opacity,
+            layer: v.layer,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Coord {
+    coord: [f32; 2],
+}
+implement_vertex!(Coord, coord);
+
+pub struct Renderer {
+    to_tex: glium::Program,
+    from_tex: glium::Program,
+    textures: glium::texture::Texture2dArray,
+    accumulator: glium::texture::Texture2d,
+    quad: glium::VertexBuffer<Coord>,
+    /// The triangles, which stay uploaded until their generation changes.
+    /// The buffer only grows, so that uploading usually doesn't reallocate it.
+    vertex_buffer: Option<glium::VertexBuffer<Vertex>>,
+    vertex_count: usize,
+    uploaded: Option<u64>,
+    /// Reused to convert the triangles before they're uploaded.
+    scratch: Vec<Vertex>,
+}
+
+impl Renderer {
+    pub fn new(display: &glium::Display) -> Result<Self, String> {
+        let to_tex = glium::Program::from_source(
+            display,
+            TO_TEX_VERTEX_SHADER,
+            TO_TEX_FRAGMENT_SHADER,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+        let from_tex = glium::Program::from_source(
+            display,
+            FROM_TEX_VERTEX_SHADER,
+            FROM_TEX_FRAGMENT_SHADER,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let layer_size = render::TEXTURE_SIZE * render::TEXTURE_SIZE * 4;
+        let layers = render::textures()
+            .chunks(layer_size)
+            .map(|layer| {
+                glium::texture::RawImage2d::from_raw_rgba(
+                    layer.to_vec(),
+                    (render::TEXTURE_SIZE as u32, render::TEXTURE_SIZE as u32),
+                )
+            })
+            .collect();
+        let textures =
+            glium::texture::Texture2dArray::new(display, layers).map_err(|e| e.to_string())?;
+
+        let (width, height) = display.get_framebuffer_dimensions();
+        let accumulator = accumulator(display, width, height)?;
+
+        let quad = glium::VertexBuffer::new(
+            display,
+            &[
+                Coord { coord: [0., 0.] },
+                Coord { coord: [0., 1.] },
+                Coord { coord: [1., 1.] },
+                Coord { coord: [1., 1.] },
+                Coord { coord: [1., 0.] },
+                Coord { coord: [0., 0.] },
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            to_tex,
+            from_tex,
+            textures,
+            accumulator,
+            quad,
+            vertex_buffer: None,
+            vertex_count: 0,
+            uploaded: None,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn draw(&mut self, display: &glium::Display, scene: &Scene) -> Result<(), String> {
+        use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+
+        if self.uploaded != Some(scene.generation) {
+            self.upload(display, scene.triangles)?;
+            self.uploaded = Some(scene.generation);
+        }
+
+        let screen = scene.screen;
+        let mut proj_3d_screen = [[0f32; 4]; 4];
+        for (c, column) in proj_3d_screen.iter_mut().enumerate() {
+            for (r, x) in column.iter_mut().enumerate() {
+                *x = screen[(r, c)] as f32;
+            }
+        }
+
+        let additive = glium::BlendingFunction::Addition {
+            source: glium::LinearBlendingFactor::One,
+            destination: glium::LinearBlendingFactor::One,
+        };
+
+        // Follow the size of the window.
+        let (width, height) = display.get_framebuffer_dimensions();
+        if self.accumulator.dimensions() != (width, height) {
+            self.accumulator = accumulator(display, width, height)?;
+        }
+
+        let mut target = glium::framebuffer::SimpleFrameBuffer::new(display, &self.accumulator)
+            .map_err(|e| e.to_string())?;
+        target.clear_color(0., 0., 0., 1.);
+        if let Some(vertices) = self.vertices() {
+            target
+                .draw(
+                    vertices,
+                    glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                    &self.to_tex,
+                    &uniform! {
+                        proj_3d_screen: proj_3d_screen,
+                        tex: self.textures.sampled()
+                            .magnify_filter(MagnifySamplerFilter::Nearest)
+                            .minify_filter(MinifySamplerFilter::Nearest)
+                            .wrap_function(SamplerWrapFunction::Repeat),
+                    },
+                    &glium::DrawParameters {
+                        blend: glium::Blend {
+                            color: additive,
+                            alpha: additive,
+                            constant_value: (0., 0., 0., 0.),
+                        },
+                        ..Default::default()
+                    },
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        let mut frame = display.draw();
+        frame.clear_color(0., 0., 0., 1.);
+        let result = frame.draw(
+            &self.quad,
+            glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+            &self.from_tex,
+            &uniform! {
+                tex: self.accumulator.sampled()
+                    .magnify_filter(MagnifySamplerFilter::Nearest)
+                    .minify_filter(MinifySamplerFilter::Nearest)
+                    .wrap_function(SamplerWrapFunction::Clamp),
+            },
+            &Default::default(),
+        );
+        // The frame must be finished even if drawing failed.
+        frame.finish().map_err(|e| e.to_string())?;
+        result.map_err(|e| e.to_string())
+    }
+
+    fn upload(
+        &mut self,
+        display: &glium::Display,
+        triangles: &[render_4d::Triangle],
+    ) -> Result<(), String> {
+        self.scratch.clear();
+        self.scratch
+            .extend(render::vertices(triangles).map(Vertex::from));
+        self.vertex_count = self.scratch.len();
+
+        let capacity = self.vertex_buffer.as_ref().map_or(0, |buffer| buffer.len());
+        if self.vertex_count > capacity {
+            self.vertex_buffer = Some(
+                glium::VertexBuffer::empty_dynamic(display, self.vertex_count.next_power_of_two())
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        if let Some(vertices) = self.vertices() {
+            vertices.write(&self.scratch);
+        }
+        Ok(())
+    }
+
+    /// The part of the vertex buffer in use, or `None` if it's empty.
+    fn vertices(&self) -> Option<glium::vertex::VertexBufferSlice<'_, Vertex>> {
+        if self.vertex_count == 0 {
+            return None;
+        }
+        self.vertex_buffer.as_ref()?.slice(0..self.vertex_count)
+    }
+}
+
+/// The float buffer that the first pass renders into.
+fn accumulator(
+    display: &glium::Display,
+    width: u32,
+    height: u32,
+) -> Result<glium::texture::Texture2d, String> {
+    glium::texture::Texture2d::empty_with_format(
+        display,
+        glium::texture::UncompressedFloatFormat::F32F32F32F32,
+        glium::texture::MipmapsOption::NoMipmap,
+        width.max(1),
+        height.max(1),
+    )
+    .map_err(|e| e.to_string())
+}
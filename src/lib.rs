@@ -1,34 +1,89 @@
 #![forbid(unsafe_code)]
 // #![allow(dead_code)]
 
-mod fps;
 mod model;
 mod render;
 mod utils;
-mod world;
+mod worker;
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
+use four_dimensions_core::{generate, level, world};
 use model::{Model, Msg};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// A running game, which JavaScript can ask for screenshots.
 #[wasm_bindgen]
-pub fn run() -> Result<(), JsValue> {
+pub struct GameHandle {
+    state: Rc<RefCell<Model>>,
+}
+
+#[wasm_bindgen]
+impl GameHandle {
+    /// The current view, as a PNG file.
+    pub fn screenshot(&self) -> Result<Vec<u8>, JsValue> {
+        let image = self.state.borrow_mut().capture()?.image;
+        image.to_png().map_err(|err| JsValue::from_str(&err))
+    }
+
+    /// The float buffer from the first rendering pass, before tone mapping, as a PFM file.
+    pub fn accumulation(&self) -> Result<Vec<u8>, JsValue> {
+        Ok(self.state.borrow_mut().capture()?.accumulation.to_pfm())
+    }
+
+    /// The current controls, in the format described in `core/src/controls.rs`.
+    pub fn controls(&self) -> String {
+        self.state.borrow().controls()
+    }
+
+    /// Change the controls, and remember them for next time.
+    /// An empty string goes back to the default controls.
+    pub fn set_controls(&self, text: &str) -> Result<(), JsValue> {
+        self.state.borrow_mut().set_controls(text)
+    }
+}
+
+#[wasm_bindgen]
+pub fn run() -> Result<GameHandle, JsValue> {
+    run_level(level::DEFAULT_LEVEL)
+}
+
+/// Run the game on a level, in either of the formats described in `core/src/level.rs`.
+#[wasm_bindgen]
+pub fn run_level(level: &[u8]) -> Result<GameHandle, JsValue> {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    let level = level.to_vec();
+    run_world(Box::new(move || Ok(world::World::load(&level)?)))
+}
+
+/// Run the game on a procedurally generated world.
+#[wasm_bindgen]
+pub fn run_generated(seed: u32) -> Result<GameHandle, JsValue> {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    run_world(Box::new(move || {
+        Ok(generate::Generator::default().generate(
+            seed.into(),
+            generate::DEFAULT_MIN,
+            generate::DEFAULT_MAX,
+        ))
+    }))
+}
 
-    let state: Rc<RefCell<Model>> = Rc::new(RefCell::new(Model::init()?));
+/// Run the game on the world made by `make_world`, which is called again to restart.
+fn run_world(make_world: model::MakeWorld) -> Result<GameHandle, JsValue> {
+    let state: Rc<RefCell<Model>> = Rc::new(RefCell::new(Model::init(make_world)?));
     let mut model = state.borrow_mut();
 
     // Handle clicks
     {
         let state = state.clone();
         let closure: Closure<dyn FnMut(web_sys::MouseEvent)> =
-            Closure::wrap(Box::new(move |_evt| {
+            Closure::wrap(Box::new(move |evt| {
                 state
                     .borrow_mut()
-                    .update(Msg::Click)
+                    .update(Msg::MouseDown(evt.button()))
                     .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
             }));
         model
@@ -37,6 +92,69 @@ pub fn run() -> Result<(), JsValue> {
         closure.forget();
     }
 
+    // Handle mouse button releases
+    {
+        let state = state.clone();
+        let closure: Closure<dyn FnMut(web_sys::MouseEvent)> =
+            Closure::wrap(Box::new(move |evt| {
+                state
+                    .borrow_mut()
+                    .update(Msg::MouseUp(evt.button()))
+                    .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+            }));
+        model
+            .document
+            .add_event_listener_with_callback("mouseup", closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
+    // Right-clicking places a block, so it shouldn't open a menu
+    {
+        let closure: Closure<dyn FnMut(web_sys::MouseEvent)> =
+            Closure::wrap(Box::new(move |evt| {
+                evt.prevent_default();
+            }));
+        model
+            .canvas
+            .add_event_listener_with_callback("contextmenu", closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
+    // Handle touches. Stopping the browser's default handling also stops it from pretending to be a mouse.
+    for &event in &["touchstart", "touchmove", "touchend", "touchcancel"] {
+        let state = state.clone();
+        let closure: Closure<dyn FnMut(web_sys::TouchEvent)> =
+            Closure::wrap(Box::new(move |evt| {
+                evt.prevent_default();
+                let touches = evt.changed_touches();
+                let touches: Vec<web_sys::Touch> = (0..touches.length())
+                    .filter_map(|i| touches.get(i))
+                    .collect();
+                let msg = match event {
+                    "touchstart" | "touchmove" => {
+                        let touches = touches
+                            .iter()
+                            .map(|t| (t.identifier(), [t.client_x(), t.client_y()]))
+                            .collect();
+                        if event == "touchstart" {
+                            Msg::TouchStart(touches)
+                        } else {
+                            Msg::TouchMove(touches)
+                        }
+                    }
+                    _ => Msg::TouchEnd(touches.iter().map(|t| t.identifier()).collect()),
+                };
+                state
+                    .borrow_mut()
+                    .update(msg)
+                    .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+            }));
+        model
+            .canvas
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
     // Handle mouse movements
     {
         let state = state.clone();
@@ -69,14 +187,14 @@ pub fn run() -> Result<(), JsValue> {
         closure.forget();
     }
 
-    // Handle keys
+    // Handle keys, by where they are on the keyboard, so that the controls don't depend on the layout
     {
         let state = state.clone();
         let closure: Closure<dyn FnMut(web_sys::KeyboardEvent)> =
             Closure::wrap(Box::new(move |evt| {
                 state
                     .borrow_mut()
-                    .update(Msg::KeyDown(evt.key()))
+                    .update(Msg::KeyDown(evt.code()))
                     .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
             }));
         model
@@ -85,14 +203,14 @@ pub fn run() -> Result<(), JsValue> {
         closure.forget();
     }
 
-    // Handle keys
+    // Handle key releases
     {
         let state = state.clone();
         let closure: Closure<dyn FnMut(web_sys::KeyboardEvent)> =
             Closure::wrap(Box::new(move |evt| {
                 state
                     .borrow_mut()
-                    .update(Msg::KeyUp(evt.key()))
+                    .update(Msg::KeyUp(evt.code()))
                     .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
             }));
         model
@@ -101,6 +219,27 @@ pub fn run() -> Result<(), JsValue> {
         closure.forget();
     }
 
+    // Handle losing the pointer lock, e.g. by pressing escape, which pauses the game
+    {
+        let state = state.clone();
+        let closure: Closure<dyn FnMut(web_sys::Event)> = Closure::wrap(Box::new(move |_evt| {
+            state
+                .borrow_mut()
+                .update(Msg::PointerLockChange)
+                .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+        }));
+        model.document.add_event_listener_with_callback(
+            "pointerlockchange",
+            closure.as_ref().unchecked_ref(),
+        )?;
+        closure.forget();
+    }
+
+    // Handle the buttons
+    on_click(&state, &model.restart_button, Msg::Restart)?;
+    on_click(&state, &model.screenshot_button, Msg::Screenshot)?;
+    on_click(&state, &model.accumulation_button, Msg::SaveAccumulation)?;
+
     // Handle frames (frames in the sense of FPS)
     {
         let state = state.clone();
@@ -129,6 +268,30 @@ pub fn run() -> Result<(), JsValue> {
     }
 
     model.view()?;
+    drop(model);
+
+    Ok(GameHandle { state })
+}
 
+/// Send `msg` to the model whenever `button` is clicked.
+fn on_click(
+    state: &Rc<RefCell<Model>>,
+    button: &web_sys::HtmlButtonElement,
+    msg: Msg,
+) -> Result<(), JsValue> {
+    let state = state.clone();
+    let target = button.clone();
+    let closure: Closure<dyn FnMut(web_sys::MouseEvent)> = Closure::wrap(Box::new(move |_evt| {
+        // Otherwise, pressing space to jump would press the button again.
+        target
+            .blur()
+            .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+        state
+            .borrow_mut()
+            .update(msg.clone())
+            .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+    }));
+    button.add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())?;
+    closure.forget();
     Ok(())
 }
@@ -1,37 +1,48 @@
-use crate::{fps, render, world};
-use core::f64::consts::*;
-use std::collections::HashSet;
+use crate::{render, utils, worker};
+use four_dimensions_core::phase::{Event, Phase};
+use four_dimensions_core::{controls::Controls, fps, world, Game, Input};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-use nalgebra as na;
+/// Where the controls are remembered between visits, in the browser's local storage.
+const CONTROLS_KEY: &str = "four-dimensions-controls";
+
+/// Builds the world to play in, at the start and again whenever the game restarts.
+pub type MakeWorld = Box<dyn Fn() -> Result<world::World, JsValue>>;
 
 /// All of the information stored by the program
 pub struct Model {
-    keys: HashSet<String>,
     fps: Option<fps::FrameCounter>,
     //
     pub window: web_sys::Window,
     pub document: web_sys::Document,
     pub canvas: web_sys::HtmlCanvasElement,
     pub info_box: web_sys::HtmlParagraphElement,
+    pub screenshot_button: web_sys::HtmlButtonElement,
+    pub accumulation_button: web_sys::HtmlButtonElement,
+    /// Covers the canvas except while playing, with a heading and a message.
+    overlay: web_sys::HtmlElement,
+    heading: web_sys::HtmlElement,
+    message: web_sys::HtmlElement,
+    pub restart_button: web_sys::HtmlButtonElement,
+    /// The phase the overlay was last updated for.
+    shown: Option<Phase>,
 
     #[allow(clippy::type_complexity)]
-    render: Box<dyn Fn(&[render_4d::Triangle], render::Mat4Wrapper) -> Result<(), JsValue>>,
-    occluded_mesh: Option<Vec<render_4d::Triangle>>,
-
-    world: world::World,
-    //
-    screen_theta: f64,
-    screen_phi: f64,
-    //
-    position: na::Vector4<f64>,
-    horizontal_orientation: na::UnitQuaternion<f64>,
-    vertical_angle: f64,
+    render: Box<dyn Fn(&[render_4d::Triangle], u64, render::Mat4Wrapper) -> Result<(), JsValue>>,
+    capture: Box<dyn Fn() -> Result<render::Capture, JsValue>>,
+
+    make_world: MakeWorld,
+    game: Game,
+    /// Whether a gamepad was connected as of the last frame.
+    gamepad: bool,
+    /// The rings and knobs of the touch joysticks, which are only shown once the canvas has been touched.
+    joysticks: [(web_sys::HtmlElement, web_sys::HtmlElement); 2],
+    touched: bool,
 }
 
 impl Model {
-    pub fn init() -> Result<Self, JsValue> {
+    pub fn init(make_world: MakeWorld) -> Result<Self, JsValue> {
         let window = web_sys::window().ok_or("no global `window` exists")?;
         let document = window
             .document()
@@ -41,111 +52,266 @@ impl Model {
         let canvas = document
             .create_element("canvas")?
             .dyn_into::<web_sys::HtmlCanvasElement>()?;
-        canvas.set_attribute("width", "800")?;
-        canvas.set_attribute("height", "800")?;
-        body.append_child(&canvas)?;
+        // The canvas's resolution follows its size on the page; see `resize`.
+        // Touches control the game, rather than scrolling or zooming the page.
+        canvas.set_attribute(
+            "style",
+            "display: block; width: 100%; height: 85vh; touch-action: none",
+        )?;
+        // The overlay is positioned relative to this, so that it lies over the canvas.
+        let container = document.create_element("div")?;
+        container.set_attribute("style", "position: relative")?;
+        container.append_child(&canvas)?;
+        body.append_child(&container)?;
+
+        let overlay = document
+            .create_element("div")?
+            .dyn_into::<web_sys::HtmlElement>()?;
+        // Clicks go through to the canvas, to capture the mouse, except on the button.
+        overlay.set_attribute(
+            "style",
+            "position: absolute; top: 0; left: 0; width: 100%; height: 100%; \
+             display: flex; flex-direction: column; align-items: center; justify-content: center; \
+             background: rgba(0, 0, 0, 0.6); color: white; font-family: sans-serif; \
+             text-align: center; pointer-events: none",
+        )?;
+        container.append_child(&overlay)?;
+        let heading = document
+            .create_element("h1")?
+            .dyn_into::<web_sys::HtmlElement>()?;
+        overlay.append_child(&heading)?;
+        let message = document
+            .create_element("p")?
+            .dyn_into::<web_sys::HtmlElement>()?;
+        overlay.append_child(&message)?;
+        let restart_button = document
+            .create_element("button")?
+            .dyn_into::<web_sys::HtmlButtonElement>()?;
+        restart_button.set_inner_text("Restart");
+        restart_button.set_attribute("style", "pointer-events: auto")?;
+        overlay.append_child(&restart_button)?;
+
+        let circle = || -> Result<web_sys::HtmlElement, JsValue> {
+            let element = document
+                .create_element("div")?
+                .dyn_into::<web_sys::HtmlElement>()?;
+            element.set_attribute("style", "display: none")?;
+            body.append_child(&element)?;
+            Ok(element)
+        };
+        let joysticks = [(circle()?, circle()?), (circle()?, circle()?)];
 
         let info_box = document
             .create_element("p")?
             .dyn_into::<web_sys::HtmlParagraphElement>()?;
         body.append_child(&info_box)?;
 
-        let render = Box::new(render::make_fn(&canvas)?);
-
-        let world = world::World::new();
+        let screenshot_button = document
+            .create_element("button")?
+            .dyn_into::<web_sys::HtmlButtonElement>()?;
+        screenshot_button.set_inner_text("Save screenshot (PNG)");
+        body.append_child(&screenshot_button)?;
+
+        let accumulation_button = document
+            .create_element("button")?
+            .dyn_into::<web_sys::HtmlButtonElement>()?;
+        accumulation_button.set_inner_text("Save float buffer (PFM)");
+        body.append_child(&accumulation_button)?;
+
+        let world = make_world()?;
+        let (render, capture) = render::make_fn(&canvas)?;
+        let projector = worker::WorkerProjector::new(&world)?;
+        let mut game = Game::with_projector(world.clone(), Box::new(projector));
+
+        // Controls saved by an older version might not load; fall back to the defaults.
+        if let Some(text) = window
+            .local_storage()?
+            .and_then(|s| s.get_item(CONTROLS_KEY).ok()?)
+        {
+            match Controls::load(&text) {
+                Ok(controls) => game.controls = controls,
+                Err(err) => {
+                    web_sys::console::warn_1(&format!("Ignoring saved controls: {}", err).into())
+                }
+            }
+        }
 
         Ok(Model {
-            keys: HashSet::new(),
             fps: None,
             //
             window,
             document,
             canvas,
             info_box,
-            render,
-            occluded_mesh: None,
-            world,
-            //
-            screen_theta: 0.3,
-            screen_phi: -0.2,
-            //
-            position: na::Vector4::new(1.5, 1.5, 1.5, 1.5),
-            horizontal_orientation: na::UnitQuaternion::new(na::Vector3::new(0., 0., 0.)),
-            vertical_angle: 0.,
+            screenshot_button,
+            accumulation_button,
+            overlay,
+            heading,
+            message,
+            restart_button,
+            shown: None,
+            render: Box::new(render),
+            capture: Box::new(capture),
+            make_world,
+            game,
+            gamepad: false,
+            joysticks,
+            touched: false,
         })
     }
 
-    pub fn view(&mut self) -> Result<(), JsValue> {
-        // web_sys::console::time_with_label("view");
-
-        let occluded_mesh: &[render_4d::Triangle];
+    pub fn controls(&self) -> String {
+        self.game.controls.save()
+    }
 
-        if let Some(x) = &self.occluded_mesh {
-            occluded_mesh = x;
+    pub fn set_controls(&mut self, text: &str) -> Result<(), JsValue> {
+        let storage = self.window.local_storage()?.ok_or("no local storage")?;
+        if text.trim().is_empty() {
+            self.game.controls = Controls::default();
+            storage.remove_item(CONTROLS_KEY)
         } else {
-            self.occluded_mesh = Some(
-                self.world
-                    .mesh()
-                    .project(self.projection_matrix())
-                    .collect(),
-            );
-            occluded_mesh = &self.occluded_mesh.as_ref().unwrap_throw(); // Is there a better way to do this?
+            self.game.controls = Controls::load(text)?;
+            storage.set_item(CONTROLS_KEY, text)
         }
+    }
 
-        (self.render)(occluded_mesh, self.screen_matrix().into())?;
+    /// Match the canvas's resolution to its size on the page, in physical pixels.
+    fn resize(&mut self) {
+        let ratio = self.window.device_pixel_ratio();
+        let width = (f64::from(self.canvas.client_width()) * ratio).round() as u32;
+        let height = (f64::from(self.canvas.client_height()) * ratio).round() as u32;
+        if (width, height) != (self.canvas.width(), self.canvas.height()) {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+            self.game.resize(f64::from(width), f64::from(height));
+        }
+    }
+
+    pub fn view(&mut self) -> Result<(), JsValue> {
+        self.resize();
+
+        // web_sys::console::time_with_label("view");
+
+        let scene = self.game.scene();
+        (self.render)(scene.triangles, scene.generation, scene.screen.into())?;
 
         // web_sys::console::time_end_with_label("view");
 
         Ok(())
     }
 
-    pub fn needs_rerender(&mut self) {
-        self.occluded_mesh = None;
+    /// Render the current view, and read it back.
+    pub fn capture(&mut self) -> Result<render::Capture, JsValue> {
+        self.view()?;
+        (self.capture)()
     }
 
     pub fn update(&mut self, msg: Msg) -> Result<(), JsValue> {
         match msg {
-            Msg::Click => {
-                if !self.pointer_lock() {
+            Msg::MouseDown(button) => {
+                if self.pointer_lock() {
+                    self.game.update(Input::MouseDown(button));
+                } else {
                     self.canvas.request_pointer_lock();
                 }
             }
-            Msg::KeyDown(k) => {
-                self.keys.insert(k.to_lowercase());
-            }
+            Msg::MouseUp(button) => self.game.update(Input::MouseUp(button)),
+            Msg::KeyDown(k) => self.game.update(Input::KeyDown(k)),
             Msg::MouseMove([x, y]) => {
                 if self.pointer_lock() {
-                    self.horizontal_orientation *=
-                        na::UnitQuaternion::new(na::Vector3::new(0., f64::from(x) * 3e-3, 0.));
-                    self.vertical_angle -= f64::from(y) * 3e-3;
-                    self.vertical_angle = self.vertical_angle.min(FRAC_PI_2);
-                    self.vertical_angle = self.vertical_angle.max(-FRAC_PI_2);
-                    self.needs_rerender();
+                    self.game
+                        .update(Input::MouseMove([f64::from(x), f64::from(y)]));
                 }
             }
             Msg::MouseWheel(z) => {
                 if self.pointer_lock() {
-                    self.horizontal_orientation *=
-                        na::UnitQuaternion::new(na::Vector3::new(z * 1e-2, 0., 0.));
+                    self.game.update(Input::MouseWheel(z));
                 }
-                self.needs_rerender();
             }
-            Msg::KeyUp(k) => {
-                self.keys.remove(&k.to_lowercase());
+            Msg::KeyUp(k) => self.game.update(Input::KeyUp(k)),
+            Msg::TouchStart(touches) => {
+                // Without pointer lock, touching the canvas is what starts the game.
+                self.game.handle(Event::Start);
+                self.game.handle(Event::Resume);
+                self.touched = true;
+                for (id, position) in touches {
+                    let position = self.touch_position(position);
+                    self.game.update(Input::TouchStart {
+                        id: id.into(),
+                        position,
+                    });
+                }
+            }
+            Msg::TouchMove(touches) => {
+                for (id, position) in touches {
+                    let position = self.touch_position(position);
+                    self.game.update(Input::TouchMove {
+                        id: id.into(),
+                        position,
+                    });
+                }
+            }
+            Msg::TouchEnd(ids) => {
+                for id in ids {
+                    self.game.update(Input::TouchEnd { id: id.into() });
+                }
+            }
+            Msg::PointerLockChange => {
+                if self.pointer_lock() {
+                    self.game.handle(Event::Start);
+                    self.game.handle(Event::Resume);
+                } else {
+                    self.game.handle(Event::Pause);
+                }
+            }
+            Msg::Restart => {
+                if self.game.phase().can_restart() {
+                    let world = (self.make_world)()?;
+                    let projector = worker::WorkerProjector::new(&world)?;
+                    self.game.restart(world, Box::new(projector));
+                    if !self.touched {
+                        self.canvas.request_pointer_lock();
+                    }
+                }
+            }
+            Msg::Screenshot => {
+                let png = self
+                    .capture()?
+                    .image
+                    .to_png()
+                    .map_err(|err| JsValue::from_str(&err))?;
+                utils::download(&self.document, &png, "screenshot.png", "image/png")?;
+            }
+            Msg::SaveAccumulation => {
+                let pfm = self.capture()?.accumulation.to_pfm();
+                utils::download(
+                    &self.document,
+                    &pfm,
+                    "accumulation.pfm",
+                    "application/octet-stream",
+                )?;
             }
             Msg::Frame(time) => {
-                let dt: f64;
                 if let Some(fps) = &mut self.fps {
-                    dt = fps.frame(time);
-
-                    self.info_box.set_inner_text(&format!("{}", fps));
-
-                    self.rotate_screen(dt);
-                    self.move_player(dt);
-
-                    self.eat_block();
-
+                    let dt = fps.frame(time);
+
+                    let (eaten, left) = self.game.tesseracts();
+                    self.info_box.set_inner_text(&format!(
+                        "{}\ntesseracts: {} eaten, {} left\nmovement: {:?} (press F to change)\nfree look: {} (press L to change, H to level)\nblock: {} (press 1-{} to change)",
+                        fps,
+                        eaten,
+                        left,
+                        self.game.movement,
+                        if self.game.free_look { "on" } else { "off" },
+                        self.game.selected.material().name,
+                        world::Block::ALL.len() - 1
+                    ));
+
+                    self.poll_gamepad();
+                    self.game.advance(dt);
                     self.view()?;
+                    self.draw_joysticks()?;
+                    self.draw_overlay()?;
                 } else {
                     self.fps = Some(<fps::FrameCounter>::new(time));
                 }
@@ -154,179 +320,155 @@ impl Model {
         Ok(())
     }
 
-    fn pointer_lock(&self) -> bool {
-        self.document.pointer_lock_element().is_some()
-    }
-
-    fn rotate_screen(&mut self, dt: f64) {
-        if self.keys.contains("arrowleft") {
-            self.screen_theta -= dt;
-        }
-        if self.keys.contains("arrowright") {
-            self.screen_theta += dt;
-        }
-        if self.keys.contains("arrowup") {
-            self.screen_phi += dt;
-            self.screen_phi = self.screen_phi.min(FRAC_PI_2)
-        }
-        if self.keys.contains("arrowdown") {
-            self.screen_phi -= dt;
-            self.screen_phi = self.screen_phi.max(-FRAC_PI_2)
+    /// The browser doesn't send events for gamepads, so ask for the state of the first one each frame.
+    fn poll_gamepad(&mut self) {
+        // Without a secure context or permission, there are no gamepads.
+        let gamepad = match self.window.navigator().get_gamepads() {
+            Ok(gamepads) => gamepads
+                .iter()
+                .filter_map(|gamepad| gamepad.dyn_into::<web_sys::Gamepad>().ok())
+                .find(|gamepad| gamepad.connected()),
+            Err(_) => None,
+        };
+
+        if let Some(gamepad) = gamepad {
+            let buttons = gamepad
+                .buttons()
+                .iter()
+                .map(|button| {
+                    button
+                        .dyn_into::<web_sys::GamepadButton>()
+                        .is_ok_and(|button| button.pressed())
+                })
+                .collect();
+            let axes = gamepad
+                .axes()
+                .iter()
+                .map(|axis| axis.as_f64().unwrap_or(0.))
+                .collect();
+            self.game.update(Input::Gamepad { buttons, axes });
+            self.gamepad = true;
+        } else if self.gamepad {
+            self.game.update(Input::Gamepad {
+                buttons: Vec::new(),
+                axes: Vec::new(),
+            });
+            self.gamepad = false;
         }
     }
 
-    fn move_player(&mut self, dt: f64) {
-        let m = self.horizontal_rotation().matrix() * dt;
-        if self.keys.contains("w") {
-            self.position += m * na::Vector4::w();
-            self.needs_rerender();
-        }
-        if self.keys.contains("s") {
-            self.position -= m * na::Vector4::w();
-            self.needs_rerender();
-        }
-        if self.keys.contains("d") {
-            self.position += m * na::Vector4::x();
-            self.needs_rerender();
+    /// Show the screen for the current phase over the canvas, if it has changed.
+    fn draw_overlay(&mut self) -> Result<(), JsValue> {
+        let phase = self.game.phase();
+        if self.shown == Some(phase) {
+            return Ok(());
         }
-        if self.keys.contains("a") {
-            self.position -= m * na::Vector4::x();
-            self.needs_rerender();
-        }
-        if self.keys.contains(" ") {
-            self.position += m * na::Vector4::y();
-            self.needs_rerender();
-        }
-        if self.keys.contains("shift") {
-            self.position -= m * na::Vector4::y();
-            self.needs_rerender();
-        }
-        if self.keys.contains("q") {
-            self.position += m * na::Vector4::z();
-            self.needs_rerender();
-        }
-        if self.keys.contains("e") {
-            self.position -= m * na::Vector4::z();
-            self.needs_rerender();
-        }
-    }
+        self.shown = Some(phase);
 
-    fn screen_matrix(&self) -> na::Matrix4<f64> {
-        na::Matrix4::new(
-            1.,
-            0.,
-            0.,
-            0.,
-            0.,
-            self.screen_phi.cos(),
-            -self.screen_phi.sin(),
-            0.,
-            0.,
-            self.screen_phi.sin(),
-            self.screen_phi.cos(),
-            0.,
-            0.,
-            0.,
-            0.,
-            1.,
-        ) * na::Matrix4::new(
-            self.screen_theta.cos(),
-            0.,
-            -self.screen_theta.sin(),
-            0.,
-            0.,
-            1.,
-            0.,
-            0.,
-            self.screen_theta.sin(),
-            0.,
-            self.screen_theta.cos(),
-            0.,
-            0.,
-            0.,
-            0.,
-            1.,
-        )
-    }
+        // The player needs the mouse back, to press the button.
+        if phase == Phase::Won && self.pointer_lock() {
+            self.document.exit_pointer_lock();
+        }
 
-    fn horizontal_rotation(&self) -> na::Rotation<f64, na::U4> {
-        let mut out = self
-            .horizontal_orientation
-            .to_rotation_matrix()
-            .matrix()
-            .insert_row(1, 0.)
-            .insert_column(1, 0.);
-        out[(1, 1)] = 1.;
-        na::Rotation::from_matrix_unchecked(out)
+        let (eaten, left) = self.game.tesseracts();
+        let seconds = self.game.time().round() as u64;
+        let message = match phase {
+            Phase::Title => format!(
+                "Eat all {} tesseracts by walking into them.\nClick or tap to start.",
+                eaten + left
+            ),
+            Phase::Playing => String::new(),
+            Phase::Paused => "Click to carry on.".to_string(),
+            Phase::Won => format!(
+                "You ate {} tesseracts in {}:{:02}.",
+                eaten,
+                seconds / 60,
+                seconds % 60
+            ),
+            Phase::Restarting => "Building the world...".to_string(),
+        };
+
+        let display = if phase == Phase::Playing {
+            "none"
+        } else {
+            "flex"
+        };
+        self.overlay.style().set_property("display", display)?;
+        self.heading
+            .set_inner_text(phase.heading().unwrap_or_default());
+        self.message.set_inner_text(&message);
+        let display = if phase.can_restart() {
+            "inline-block"
+        } else {
+            "none"
+        };
+        self.restart_button
+            .style()
+            .set_property("display", display)?;
+        Ok(())
     }
 
-    fn vertical_rotation(&self) -> na::Rotation<f64, na::U4> {
-        let mut out = na::Matrix4::identity();
-        out[(1, 1)] = self.vertical_angle.cos();
-        out[(1, 3)] = self.vertical_angle.sin();
-        out[(3, 1)] = -self.vertical_angle.sin();
-        out[(3, 3)] = self.vertical_angle.cos();
-        na::Rotation::from_matrix_unchecked(out)
+    /// Where a touch is, in client coordinates, as a fraction of the canvas's width and height.
+    fn touch_position(&self, [x, y]: [i32; 2]) -> [f64; 2] {
+        let rect = self.canvas.get_bounding_client_rect();
+        [
+            (f64::from(x) - rect.left()) / rect.width(),
+            (f64::from(y) - rect.top()) / rect.height(),
+        ]
     }
 
-    fn projection_matrix(&self) -> na::Matrix5<f64> {
-        let cotangent_half_fov = 0.5;
-        let projection: na::Matrix5<f64> = na::Matrix5::new(
-            cotangent_half_fov,
-            0.,
-            0.,
-            0.,
-            0.,
-            0.,
-            cotangent_half_fov,
-            0.,
-            0.,
-            0.,
-            0.,
-            0.,
-            cotangent_half_fov,
-            0.,
-            0.,
-            0.,
-            0.,
-            0.,
-            0.,
-            -1.,
-            0.,
-            0.,
-            0.,
-            1.,
-            0.,
-        );
-
-        let isometry = na::Isometry::from_parts(
-            na::Translation {
-                vector: self.position,
-            },
-            self.horizontal_rotation() * self.vertical_rotation(),
-        )
-        .inverse();
-        projection * isometry.to_homogeneous()
+    /// Draw the touch joysticks over the canvas, once it has been touched.
+    fn draw_joysticks(&self) -> Result<(), JsValue> {
+        if !self.touched {
+            return Ok(());
+        }
+        let rect = self.canvas.get_bounding_client_rect();
+        let side = rect.width().min(rect.height());
+        let circle =
+            |element: &web_sys::HtmlElement, [x, y]: [f64; 2], radius: f64, opacity: f64| {
+                element.set_attribute(
+                    "style",
+                    &format!(
+                        "position: fixed; left: {}px; top: {}px; width: {}px; height: {}px; \
+                     border-radius: 50%; background: white; opacity: {}; pointer-events: none",
+                        rect.left() + x * rect.width() - radius,
+                        rect.top() + y * rect.height() - radius,
+                        2. * radius,
+                        2. * radius,
+                        opacity,
+                    ),
+                )
+            };
+        for (joystick, (ring, knob)) in self.game.joysticks().iter().zip(&self.joysticks) {
+            circle(ring, joystick.center, joystick.radius * side, 0.2)?;
+            let opacity = if joystick.held { 0.6 } else { 0.4 };
+            circle(knob, joystick.knob, joystick.radius * side / 2., opacity)?;
+        }
+        Ok(())
     }
 
-    fn eat_block(&mut self) {
-        let [x, y, z, w]: [f64; 4] = self.position.into();
-        if let Some(block) = self.world.block_mut([
-            x.floor() as isize,
-            y.floor() as isize,
-            z.floor() as isize,
-            w.floor() as isize,
-        ]) {
-            *block = world::Block::Air;
-        }
+    fn pointer_lock(&self) -> bool {
+        self.document.pointer_lock_element().is_some()
     }
 }
 
+#[derive(Clone)]
 pub enum Msg {
-    Click,
+    MouseDown(i16),
+    MouseUp(i16),
     Frame(f64), // time in milliseconds, counted from the start of the program.
     MouseMove([i32; 2]),
     MouseWheel(f64),
     KeyDown(String),
     KeyUp(String),
+    /// Touches that started or moved, by their identifier and position in client coordinates.
+    TouchStart(Vec<(i32, [i32; 2])>),
+    TouchMove(Vec<(i32, [i32; 2])>),
+    /// Touches that ended or were cancelled, by their identifier.
+    TouchEnd(Vec<i32>),
+    /// The mouse was captured or released.
+    PointerLockChange,
+    Restart,
+    Screenshot,
+    SaveAccumulation,
 }
@@ -4,6 +4,8 @@ mod shader;
 mod from_tex;
 mod to_tex;
 
+use four_dimensions_core::image::Image;
+use std::cell::Cell;
 use std::rc::Rc;
 pub use to_tex::Mat4Wrapper;
 use wasm_bindgen::prelude::*;
@@ -11,10 +13,25 @@ use wasm_bindgen::JsCast;
 
 type GL = web_sys::WebGl2RenderingContext;
 
+/// What was on the screen, as read back from the GPU.
+pub struct Capture {
+    /// The picture on the canvas.
+    pub image: Image<u8>,
+    /// The float buffer that `to_tex` renders into.
+    pub accumulation: Image<f32>,
+}
+
+/// Returns a function to render triangles, and a function to read back what was last rendered.
+/// The triangles are only uploaded again when their generation changes; see `Scene::generation`.
+/// The latter must be called in the same frame as the former, before the browser clears the canvas.
+#[allow(clippy::type_complexity)]
 pub fn make_fn(
     canvas: &web_sys::HtmlCanvasElement,
 ) -> Result<
-    impl 'static + Fn(&[render_4d::Triangle], to_tex::Mat4Wrapper) -> Result<(), JsValue>,
+    (
+        impl 'static + Fn(&[render_4d::Triangle], u64, to_tex::Mat4Wrapper) -> Result<(), JsValue>,
+        impl 'static + Fn() -> Result<Capture, JsValue>,
+    ),
     JsValue,
 > {
     let gl = canvas
@@ -32,30 +49,102 @@ pub fn make_fn(
     gl.bind_texture(GL::TEXTURE_2D, Some(&tex));
     gl.pixel_storei(GL::UNPACK_ALIGNMENT, 1);
 
-    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-        GL::TEXTURE_2D,
-        0,                  // level
-        GL::RGBA32F as i32, // internal_format
-        800,                // width
-        800,                // height
-        0,                  // border
-        GL::RGBA,           // format
-        GL::FLOAT,          // type
-        None,
-    )?;
-
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
 
+    // For reading the texture back.
+    let framebuffer = gl.create_framebuffer().ok_or("create_framebuffer failed")?;
+    gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+    gl.framebuffer_texture_2d(
+        GL::FRAMEBUFFER,
+        GL::COLOR_ATTACHMENT0,
+        GL::TEXTURE_2D,
+        Some(&tex),
+        0,
+    );
+
     let gl = Rc::new(gl);
     let to_tex = to_tex::make_fn(Rc::clone(&gl), &tex)?;
-    let from_tex = from_tex::make_fn(gl)?;
+    let from_tex = from_tex::make_fn(Rc::clone(&gl))?;
+
+    // The size the texture is allocated at, which follows the size of the canvas.
+    let size = Rc::new(Cell::new((0, 0)));
+
+    let render = {
+        let gl = Rc::clone(&gl);
+        let tex = tex.clone();
+        let size = Rc::clone(&size);
+        move |data: &[render_4d::Triangle], generation: u64, mat| {
+            let (width, height) = (gl.drawing_buffer_width(), gl.drawing_buffer_height());
+            if size.get() != (width, height) {
+                gl.bind_texture(GL::TEXTURE_2D, Some(&tex));
+                gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                    GL::TEXTURE_2D,
+                    0,                  // level
+                    GL::RGBA32F as i32, // internal_format
+                    width,              // width
+                    height,             // height
+                    0,                  // border
+                    GL::RGBA,           // format
+                    GL::FLOAT,          // type
+                    None,
+                )?;
+                size.set((width, height));
+            }
+
+            to_tex(data, generation, mat)?;
+            from_tex(&tex);
+            Ok(())
+        }
+    };
+
+    let capture = move || {
+        let (width, height) = size.get();
+        // A hidden or collapsed canvas has nothing to read back.
+        if width == 0 || height == 0 {
+            return Err(JsValue::from_str(
+                "The canvas is empty, so there is nothing to capture",
+            ));
+        }
+        let length = (width * height * 4) as u32;
+
+        let pixels = js_sys::Uint8Array::new_with_length(length);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        gl.read_pixels_with_opt_array_buffer_view(
+            0,
+            0,
+            width,
+            height,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&pixels),
+        )?;
+        let mut image = Image::from_gl(width as usize, height as usize, &pixels.to_vec());
+        // Save the picture as opaque, like the software rasterizer does.
+        for pixel in &mut image.pixels {
+            pixel[3] = 255;
+        }
+
+        let floats = js_sys::Float32Array::new_with_length(length);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+        gl.read_pixels_with_opt_array_buffer_view(
+            0,
+            0,
+            width,
+            height,
+            GL::RGBA,
+            GL::FLOAT,
+            Some(&floats),
+        )?;
+        let accumulation = Image::from_gl(width as usize, height as usize, &floats.to_vec());
+
+        Ok(Capture {
+            image,
+            accumulation,
+        })
+    };
 
-    Ok(move |data: &[render_4d::Triangle], mat| {
-        to_tex(data, mat)?;
-        from_tex(&tex);
-        Ok(())
-    })
+    Ok((render, capture))
 }
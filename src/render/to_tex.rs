@@ -1,5 +1,7 @@
 use super::program::Program;
 use crate::utils::as_f32_array;
+use four_dimensions_core::render::{self, FLOATS_PER_VERTEX};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
@@ -10,15 +12,24 @@ const VERTEX_SHADER: &str = r#"#version 300 es
 in vec4 pos;
 in vec3 texcoord;
 in float sign;
+in vec3 color;
+in float opacity;
+in float layer;
 
 out vec3 vtexcoord;
 out float vsign;
+out vec3 vcolor;
+out float vopacity;
+flat out float vlayer;
 
 uniform mat4 proj_3d_screen;
 
 void main() {
     vsign = sign;
     vtexcoord = texcoord;
+    vcolor = color;
+    vopacity = opacity;
+    vlayer = layer;
     gl_Position = proj_3d_screen * pos;
 }
 
@@ -26,45 +37,43 @@ void main() {
 
 const FRAGMENT_SHADER: &str = r#"#version 300 es
 
-precision mediump float;
+// The material is encoded in the texture coordinates, which can be too large for mediump.
+precision highp float;
+precision mediump sampler2DArray;
 
 in vec3 vtexcoord;
 in float vsign;
+in vec3 vcolor;
+in float vopacity;
+flat in float vlayer;
 
 out vec4 color;
 
-uniform sampler2D tex;
+uniform sampler2DArray tex;
 
 void main() {
-    color = vec4((1.0 - texture(tex, vtexcoord.xy / vtexcoord.z).rgb) * 0.5 * vsign, 1.0);
+    vec3 texel = texture(tex, vec3(vtexcoord.xy / vtexcoord.z, vlayer)).rgb;
+    color = vec4((1.0 - texel * vcolor) * vopacity * 0.5 * vsign, 1.0);
 }
 
 "#;
 
-fn iter_triangles(triangles: &[render_4d::Triangle]) -> impl Iterator<Item = f64> + '_ {
-    triangles
-        .iter()
-        .flat_map(|render_4d::Triangle { vertices, negated }| {
-            let sign = if *negated { &-1.0 } else { &1.0 };
-            vertices
-                .iter()
-                .flat_map(move |render_4d::Vertex { position, texcoord }| {
-                    position.iter().chain(texcoord).chain(std::iter::once(sign))
-                })
-        })
-        .copied()
-}
-
+#[allow(clippy::type_complexity)]
 pub fn make_fn(
     gl: Rc<GL>,
     render_texture: &web_sys::WebGlTexture,
-) -> Result<impl 'static + Fn(&[render_4d::Triangle], Mat4Wrapper) -> Result<(), JsValue>, JsValue>
-{
+) -> Result<
+    impl 'static + Fn(&[render_4d::Triangle], u64, Mat4Wrapper) -> Result<(), JsValue>,
+    JsValue,
+> {
     let program = Program::new(Rc::clone(&gl), VERTEX_SHADER, FRAGMENT_SHADER)?;
 
     let pos_loc = program.attribute("pos")?;
     let texcoord_loc = program.attribute("texcoord")?;
     let sign_loc = program.attribute("sign")?;
+    let color_loc = program.attribute("color")?;
+    let opacity_loc = program.attribute("opacity")?;
+    let layer_loc = program.attribute("layer")?;
     let tex_loc = program.uniform("tex")?;
     let proj_3d_screen_loc = program.uniform("proj_3d_screen")?;
 
@@ -75,12 +84,19 @@ pub fn make_fn(
 
     let vertex_buffer = gl.create_buffer().ok_or("create_buffer failed")?;
     gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vertex_buffer));
+    let stride = FLOATS_PER_VERTEX as i32 * 4;
     gl.enable_vertex_attrib_array(pos_loc);
-    gl.vertex_attrib_pointer_with_i32(pos_loc, 4, GL::FLOAT, false, 8 * 4, 0);
+    gl.vertex_attrib_pointer_with_i32(pos_loc, 4, GL::FLOAT, false, stride, 0);
     gl.enable_vertex_attrib_array(texcoord_loc);
-    gl.vertex_attrib_pointer_with_i32(texcoord_loc, 3, GL::FLOAT, false, 8 * 4, 4 * 4);
+    gl.vertex_attrib_pointer_with_i32(texcoord_loc, 3, GL::FLOAT, false, stride, 4 * 4);
     gl.enable_vertex_attrib_array(sign_loc);
-    gl.vertex_attrib_pointer_with_i32(sign_loc, 1, GL::FLOAT, false, 8 * 4, 7 * 4);
+    gl.vertex_attrib_pointer_with_i32(sign_loc, 1, GL::FLOAT, false, stride, 7 * 4);
+    gl.enable_vertex_attrib_array(color_loc);
+    gl.vertex_attrib_pointer_with_i32(color_loc, 3, GL::FLOAT, false, stride, 8 * 4);
+    gl.enable_vertex_attrib_array(opacity_loc);
+    gl.vertex_attrib_pointer_with_i32(opacity_loc, 1, GL::FLOAT, false, stride, 11 * 4);
+    gl.enable_vertex_attrib_array(layer_loc);
+    gl.vertex_attrib_pointer_with_i32(layer_loc, 1, GL::FLOAT, false, stride, 12 * 4);
 
     let framebuffer = gl.create_framebuffer().ok_or("create_framebuffer failed")?;
     gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
@@ -93,58 +109,94 @@ pub fn make_fn(
     );
 
     let texture = gl.create_texture().ok_or("create_texture failed")?;
-    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
-
-    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-        GL::TEXTURE_2D,
-        0,                 // level
-        GL::RGBA as i32,   // internal_format
-        256,               // width
-        256,               // height
-        0,                 // border
-        GL::RGBA,          // format
-        GL::UNSIGNED_BYTE, // type
-        Some(include_bytes!("../../resources/tex")),
+    gl.bind_texture(GL::TEXTURE_2D_ARRAY, Some(&texture));
+
+    gl.tex_image_3d_with_opt_u8_array(
+        GL::TEXTURE_2D_ARRAY,
+        0,                            // level
+        GL::RGBA as i32,              // internal_format
+        render::TEXTURE_SIZE as i32,  // width
+        render::TEXTURE_SIZE as i32,  // height
+        render::TEXTURE_COUNT as i32, // depth
+        0,                            // border
+        GL::RGBA,                     // format
+        GL::UNSIGNED_BYTE,            // type
+        Some(&render::textures()[..]),
     )?;
-    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
-    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
-    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::REPEAT as i32);
-    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::REPEAT as i32);
+    gl.tex_parameteri(
+        GL::TEXTURE_2D_ARRAY,
+        GL::TEXTURE_MIN_FILTER,
+        GL::NEAREST as i32,
+    );
+    gl.tex_parameteri(
+        GL::TEXTURE_2D_ARRAY,
+        GL::TEXTURE_MAG_FILTER,
+        GL::NEAREST as i32,
+    );
+    gl.tex_parameteri(GL::TEXTURE_2D_ARRAY, GL::TEXTURE_WRAP_S, GL::REPEAT as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D_ARRAY, GL::TEXTURE_WRAP_T, GL::REPEAT as i32);
 
-    Ok(move |data: &[render_4d::Triangle], mat: Mat4Wrapper| {
-        let data: Vec<f32> = iter_triangles(data).map(|x| x as f32).collect();
+    // The triangles stay in the vertex buffer until they change.
+    // The buffer only grows, so that uploading usually doesn't reallocate it.
+    let uploaded: Cell<Option<u64>> = Cell::new(None);
+    let capacity = Cell::new(0);
+    let vertices: RefCell<Vec<f32>> = RefCell::new(Vec::new());
 
+    let render = move |data: &[render_4d::Triangle], generation: u64, mat: Mat4Wrapper| {
         gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
         gl.bind_vertex_array(Some(&vao));
 
-        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vertex_buffer));
-        gl.buffer_data_with_array_buffer_view(
-            GL::ARRAY_BUFFER,
-            &as_f32_array(&data)?.into(),
-            GL::DYNAMIC_DRAW,
-        );
-
-        gl.viewport(0, 0, 800, 800);
+        let mut vertices = vertices.borrow_mut();
+        if uploaded.get() != Some(generation) {
+            vertices.clear();
+            for vertex in render::vertices(data) {
+                vertices.extend_from_slice(&vertex.to_array());
+            }
+
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vertex_buffer));
+            if vertices.len() > capacity.get() {
+                capacity.set(vertices.len().next_power_of_two());
+                gl.buffer_data_with_i32(
+                    GL::ARRAY_BUFFER,
+                    capacity.get() as i32 * 4,
+                    GL::DYNAMIC_DRAW,
+                );
+            }
+            gl.buffer_sub_data_with_i32_and_array_buffer_view(
+                GL::ARRAY_BUFFER,
+                0,
+                &as_f32_array(&vertices)?.into(),
+            );
+            uploaded.set(Some(generation));
+        }
+
+        gl.viewport(0, 0, gl.drawing_buffer_width(), gl.drawing_buffer_height());
         gl.clear_color(0., 0., 0., 1.);
         gl.clear(GL::COLOR_BUFFER_BIT);
 
         gl.use_program(Some(&program));
         gl.bind_vertex_array(Some(&vao));
 
-        gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+        gl.bind_texture(GL::TEXTURE_2D_ARRAY, Some(&texture));
         gl.uniform1i(Some(&tex_loc), 0);
 
         let mat: nalgebra::Matrix4<f64> = mat.0;
-        gl.uniform_matrix4fv_with_f32_array(
-            Some(&proj_3d_screen_loc),
-            false,
-            &mat.into_iter().map(|&x| x as f32).collect::<Vec<_>>(),
+        let mut proj_3d_screen = [0f32; 16];
+        for (x, &y) in proj_3d_screen.iter_mut().zip(mat.iter()) {
+            *x = y as f32;
+        }
+        gl.uniform_matrix4fv_with_f32_array(Some(&proj_3d_screen_loc), false, &proj_3d_screen);
+
+        gl.draw_arrays(
+            GL::TRIANGLES,
+            0,
+            (vertices.len() / FLOATS_PER_VERTEX) as i32,
         );
 
-        gl.draw_arrays(GL::TRIANGLES, 0, (data.len() / 8) as i32);
-
         Ok(())
-    })
+    };
+
+    Ok(render)
 }
 
 // For some reason, it won't compile without the wrapper.
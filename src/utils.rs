@@ -11,6 +11,38 @@ pub fn as_f32_array(v: &[f32]) -> Result<js_sys::Float32Array, JsValue> {
     Ok(js_sys::Float32Array::new(&memory_buffer).subarray(location, location + v.len() as u32))
 }
 
+/// Have the browser save `data` as a file.
+pub fn download(
+    document: &web_sys::Document,
+    data: &[u8],
+    filename: &str,
+    mime_type: &str,
+) -> Result<(), JsValue> {
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &js_sys::Array::of1(&js_sys::Uint8Array::from(data)),
+        &options,
+    )?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let link = document
+        .create_element("a")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()?;
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+
+    // The browser may not have started the download yet, so only revoke the URL once it has had the chance.
+    let revoke = Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+    });
+    web_sys::window()
+        .ok_or("No window")?
+        .set_timeout_with_callback(revoke.unchecked_ref())?;
+    Ok(())
+}
+
 #[allow(dead_code)]
 pub fn log<T: core::fmt::Debug>(x: T) {
     web_sys::console::log_1(&format!("{:?}", x).into());
@@ -0,0 +1,123 @@
+//! Finding the visible triangles in a Web Worker, so that the page stays responsive while it happens.
+//!
+//! `worker.js` loads this module again inside the worker, and calls `worker_main`.
+//! The first message to the worker is the world, as a binary level.
+//! After that, each message is a `projector::Request`, and each reply is a list of triangles,
+//! both encoded as `Float64Array`s.
+
+use four_dimensions_core::level;
+use four_dimensions_core::projector::{self, Projector, Request};
+use four_dimensions_core::world::{self, Block};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// The main thread's end of the worker.
+pub struct WorkerProjector {
+    worker: web_sys::Worker,
+    result: Rc<RefCell<Option<Vec<render_4d::Triangle>>>>,
+    _on_message: Closure<dyn FnMut(web_sys::MessageEvent)>,
+}
+
+impl WorkerProjector {
+    pub fn new(world: &world::World) -> Result<Self, JsValue> {
+        let options = web_sys::WorkerOptions::new();
+        options.set_type(web_sys::WorkerType::Module);
+        let worker = web_sys::Worker::new_with_options("./worker.js", &options)?;
+
+        let result = Rc::new(RefCell::new(None));
+        let on_message: Closure<dyn FnMut(web_sys::MessageEvent)> = {
+            let result = result.clone();
+            Closure::wrap(Box::new(move |evt: web_sys::MessageEvent| {
+                let data = js_sys::Float64Array::new(&evt.data()).to_vec();
+                let triangles = projector::decode_triangles(&data)
+                    .unwrap_or_else(|err| wasm_bindgen::throw_str(&err));
+                *result.borrow_mut() = Some(triangles);
+            }))
+        };
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let level = world
+            .save(level::Format::Binary)
+            .map_err(|err| JsValue::from_str(&err))?;
+        worker.post_message(&js_sys::Uint8Array::from(&level[..]))?;
+
+        Ok(Self {
+            worker,
+            result,
+            _on_message: on_message,
+        })
+    }
+
+    fn send(&self, request: Request) {
+        self.worker
+            .post_message(&js_sys::Float64Array::from(&request.encode()[..]))
+            .unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+    }
+}
+
+impl Projector for WorkerProjector {
+    fn set_block(&mut self, pos: [isize; 4], block: Block) {
+        self.send(Request::SetBlock(pos, block));
+    }
+
+    fn request(&mut self, projection: nalgebra::Matrix5<f64>) {
+        self.send(Request::Project(projection));
+    }
+
+    fn poll(&mut self) -> Option<Vec<render_4d::Triangle>> {
+        self.result.borrow_mut().take()
+    }
+}
+
+impl Drop for WorkerProjector {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+/// The worker's entry point. `queued` holds the messages that arrived while the module was loading.
+#[wasm_bindgen]
+pub fn worker_main(queued: js_sys::Array) -> Result<(), JsValue> {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+
+    let scope = js_sys::global().dyn_into::<web_sys::DedicatedWorkerGlobalScope>()?;
+    let mut state: Option<projector::Synchronous> = None;
+
+    let mut handle = {
+        let scope = scope.clone();
+        move |data: JsValue| -> Result<(), JsValue> {
+            if let Some(level) = data.dyn_ref::<js_sys::Uint8Array>() {
+                let world = world::World::load(&level.to_vec())?;
+                state = Some(projector::Synchronous::new(world));
+                return Ok(());
+            }
+
+            let request = Request::decode(&js_sys::Float64Array::new(&data).to_vec())?;
+            let projector = state
+                .as_mut()
+                .ok_or("The worker was sent a request before a world")?;
+            if let Some(triangles) = projector.handle(request) {
+                let reply =
+                    js_sys::Float64Array::from(&projector::encode_triangles(&triangles)[..]);
+                // Hand over the buffer, rather than copying it.
+                scope.post_message_with_transfer(&reply, &js_sys::Array::of1(&reply.buffer()))?;
+            }
+            Ok(())
+        }
+    };
+
+    for data in queued.iter() {
+        handle(data)?;
+    }
+
+    let closure: Closure<dyn FnMut(web_sys::MessageEvent)> =
+        Closure::wrap(Box::new(move |evt: web_sys::MessageEvent| {
+            handle(evt.data()).unwrap_or_else(|err| wasm_bindgen::throw_val(err));
+        }));
+    scope.set_onmessage(Some(closure.as_ref().unchecked_ref()));
+    closure.forget();
+
+    Ok(())
+}
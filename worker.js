@@ -0,0 +1,8 @@
+// Finds the visible triangles off the main thread; see `src/worker.rs`.
+import init, { worker_main } from './pkg/four_dimensions.js';
+
+// Keep the messages that arrive while the module loads.
+const queued = [];
+self.onmessage = event => queued.push(event.data);
+
+init().then(() => worker_main(queued));